@@ -0,0 +1,27 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+fn collect(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() { collect(&path, files)?; } else if path.extension().is_some_and(|e| e == "rs") { files.push(path); }
+    }
+    Ok(())
+}
+
+// Bundles every source file into the listing the "cor.shop's source code" item hands out
+fn main() -> io::Result<()> {
+    println!("cargo:rerun-if-changed=src");
+    let mut files = Vec::new();
+    collect(Path::new("src"), &mut files)?;
+    files.sort();
+    let mut listing = String::new();
+    for path in files {
+        listing.push_str(&format!("// ---- {} ----\n", path.display()));
+        listing.push_str(&fs::read_to_string(&path)?);
+        listing.push('\n');
+    }
+    fs::write(PathBuf::from(env::var_os("OUT_DIR").unwrap()).join("source.txt"), listing)
+}
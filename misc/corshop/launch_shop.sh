@@ -15,4 +15,10 @@ export HEAP="0x804b000:      0x00000000      0x00000069      0x63726f63      0x7
 0x804b0d0:      0x00000000      0x00020f31      0x00000000      0x00000000
 0x804b0e0:      0x00000000      0x00000000      0x00000000      0x00000000"
 
+# The jail gives every connection a fresh /tmp (see JAIL_TMP_SIZE in the Dockerfile), so on the
+# deployed challenge accounts last for one connection on purpose and players never see each other's.
+# Point CORSHOP_STORE at a real disk, or run `cor-shop --listen`, to keep them across connections.
+export CORSHOP_STORE=/tmp/corshop.json
+export CORSHOP_CATALOG=/app/catalog.toml
+
 exec /app/target/x86_64-unknown-linux-musl/release/cor-shop
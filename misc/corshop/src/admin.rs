@@ -0,0 +1,86 @@
+use std::env;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use toml_edit::{value, ArrayOfTables, DocumentMut, InlineTable, Table};
+
+use crate::catalog::CatalogError;
+use crate::clock::{format_utc, unix_now};
+use crate::money::Corns;
+
+// Wrong tokens all sessions together may try within `ATTEMPT_WINDOW` seconds before the console
+// locks, for everyone, until the oldest of them is that far in the past
+pub const MAX_ATTEMPTS: usize = 5;
+pub const ATTEMPT_WINDOW: u64 = 600;
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AdminConfig {
+    /// Unlocks the admin console. `CORSHOP_ADMIN_TOKEN` takes precedence, and with neither set
+    /// the console stays locked.
+    pub token: Option<String>,
+    /// File every admin action is appended to, on top of the server log.
+    pub log: Option<PathBuf>,
+}
+
+impl AdminConfig {
+    pub fn token(&self) -> Option<String> {
+        env::var("CORSHOP_ADMIN_TOKEN").ok().filter(|t| !t.is_empty()).or_else(|| self.token.clone().filter(|t| !t.is_empty()))
+    }
+
+    /// Whether `given` is the admin token. Digests are compared so the time taken says nothing
+    /// about how much of the token was right.
+    pub fn check(&self, given: &str) -> bool {
+        self.token().is_some_and(|t| Sha256::digest(t.as_bytes()) == Sha256::digest(given.as_bytes()))
+    }
+
+    /// Records that `who` did `action`, with the time it happened.
+    pub fn log(&self, who: &str, action: &str) {
+        let line = format!("{} {}: {}", format_utc(unix_now()), who, action);
+        eprintln!("admin: {}", line);
+        let Some(path) = &self.log else { return };
+        if let Err(e) = OpenOptions::new().create(true).append(true).open(path).and_then(|mut f| writeln!(f, "{}", line)) {
+            eprintln!("admin: could not write {}: {}", path.display(), e);
+        }
+    }
+}
+
+fn items(doc: &mut DocumentMut) -> Result<&mut ArrayOfTables, CatalogError> {
+    if !doc.contains_key("item") { doc.insert("item", toml_edit::Item::ArrayOfTables(ArrayOfTables::new())); }
+    doc["item"].as_array_of_tables_mut().ok_or(CatalogError::NotEditable)
+}
+
+fn position(doc: &mut DocumentMut, id: u32) -> Result<usize, CatalogError> {
+    items(doc)?.iter().position(|t| t.get("id").and_then(|v| v.as_integer()) == Some(id as i64)).ok_or(CatalogError::NoSuchItem(id))
+}
+
+pub fn set_price(doc: &mut DocumentMut, id: u32, price: Corns) -> Result<(), CatalogError> {
+    let i = position(doc, id)?;
+    let item = items(doc)?.get_mut(i).ok_or(CatalogError::NoSuchItem(id))?;
+    item["price"] = value(price.get() as i64);
+    Ok(())
+}
+
+/// Appends an item that is delivered as a fixed piece of text.
+pub fn add_item(doc: &mut DocumentMut, id: u32, name: &str, price: Corns, stock: Option<u64>, text: &str) -> Result<(), CatalogError> {
+    let mut item = Table::new();
+    item["id"] = value(id as i64);
+    item["name"] = value(name);
+    item["price"] = value(price.get() as i64);
+    if let Some(stock) = stock { item["stock"] = value(stock as i64); }
+    let mut delivery = InlineTable::new();
+    delivery.insert("kind", "text".into());
+    delivery.insert("text", text.into());
+    item["delivery"] = value(delivery);
+    items(doc)?.push(item);
+    Ok(())
+}
+
+pub fn remove_item(doc: &mut DocumentMut, id: u32) -> Result<(), CatalogError> {
+    let i = position(doc, id)?;
+    items(doc)?.remove(i);
+    Ok(())
+}
@@ -0,0 +1,249 @@
+use std::fmt;
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use crate::catalog::{one, Item, Snapshot};
+use crate::fulfillment::Order;
+use crate::ledger::{user_book, Kind, Leg, ESCROW, SHOP};
+use crate::money::{Corns, MoneyError};
+use crate::store::{Purchase, State, Store};
+
+fn one_corn() -> Corns { Corns::new(1) }
+
+/// An `[[auction]]` entry in the catalog: `qty` units of `item` go to the highest bidder at `ends_at`.
+#[derive(Clone, Deserialize)]
+pub struct Auction {
+    pub id: u32,
+    pub item: u32,
+    #[serde(default = "one")]
+    pub qty: u64,
+    // Unix time bidding opens, right away if left out
+    pub starts_at: Option<u64>,
+    pub ends_at: u64,
+    // Lowest acceptable first bid
+    #[serde(default)]
+    pub reserve: Corns,
+    // How much each bid has to beat the last one by
+    #[serde(default = "one_corn")]
+    pub increment: Corns,
+}
+
+/// The bidding on one auction. The high bid is held in escrow while `bidder` is set. Settling
+/// moves the high bidder to `winner`, so nothing is left to pay out a second time.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Lot {
+    pub bidder: Option<String>,
+    pub bid: Corns,
+    pub bids: u64,
+    pub settled: bool,
+    #[serde(default)]
+    pub winner: Option<String>,
+}
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status { Upcoming, Open, Ended }
+
+impl Auction {
+    pub fn status(&self, now: u64) -> Status {
+        if self.starts_at.is_some_and(|t| now < t) { Status::Upcoming } else if now < self.ends_at { Status::Open } else { Status::Ended }
+    }
+
+    /// The smallest bid that would currently be accepted.
+    pub fn min_bid(&self, lot: Option<&Lot>) -> Result<Corns, MoneyError> {
+        match lot {
+            Some(Lot { bidder: Some(_), bid, .. }) => bid.plus(self.increment),
+            _ => Ok(self.reserve.max(Corns::new(1))),
+        }
+    }
+}
+
+pub enum BidError {
+    UnknownAuction(u32),
+    NotOpen(u32),
+    TooLow { min: Corns },
+    Insufficient { need: Corns, have: Corns },
+    Money(MoneyError),
+    Io(io::Error),
+}
+
+impl From<io::Error> for BidError { fn from(e: io::Error) -> BidError { BidError::Io(e) } }
+
+impl From<MoneyError> for BidError { fn from(e: MoneyError) -> BidError { BidError::Money(e) } }
+
+impl fmt::Display for BidError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BidError::UnknownAuction(id) => write!(f, "There is no auction #{}. Try `auction list`.", id),
+            BidError::NotOpen(id) => write!(f, "Auction #{} is not taking bids.", id),
+            BidError::TooLow { min } => write!(f, "Bid too low. The minimum bid is {}.", min),
+            BidError::Insufficient { need, have } => write!(f, "Insufficient balance. Need {}, have {}.", need, have),
+            BidError::Money(e) => write!(f, "Can't bid that: {}.", e),
+            BidError::Io(_) => write!(f, "Could not save your bid, try again later."),
+        }
+    }
+}
+
+fn item_name(snapshot: &Snapshot, id: u32) -> String {
+    snapshot.items.iter().find(|it| it.id == id).map_or_else(|| format!("item {}", id), |it| it.name.clone())
+}
+
+/// Gives the held bid on `auction` back to its bidder.
+fn release(s: &mut State, auction: u32, now: u64, why: String) -> Result<(), MoneyError> {
+    let lot = s.auctions.entry(auction).or_default();
+    let Some(bidder) = lot.bidder.take() else { return Ok(()) };
+    let amount = std::mem::take(&mut lot.bid);
+    if let Some(acct) = s.accounts.get_mut(&bidder) { acct.balance = acct.balance.plus(amount)?; }
+    let leg = Leg::Corns { from: ESCROW.to_string(), to: user_book(&bidder), amount };
+    s.record(Kind::Release, now, format!("bid on auction #{} released, {}", auction, why), vec![leg]);
+    s.notify(&bidder, now, format!("Your bid of {} corns on auction #{} was returned: {}.", amount, auction, why));
+    Ok(())
+}
+
+/// Bids `amount` on auction `id`, taking it into escrow and releasing the previous high bid.
+/// Returns the bidder's balance afterwards.
+pub fn bid(store: &Store, snapshot: &Snapshot, user: &str, id: u32, amount: Corns, now: u64) -> Result<Corns, BidError> {
+    let auction = snapshot.auction(id).ok_or(BidError::UnknownAuction(id))?;
+    store.update(|s| {
+        if !matches!(auction.status(now), Status::Open) || s.auctions.get(&id).is_some_and(|l| l.settled) {
+            return Err(BidError::NotOpen(id));
+        }
+        let min = auction.min_bid(s.auctions.get(&id))?;
+        if amount < min { return Err(BidError::TooLow { min }); }
+        // Raising your own bid only needs the difference, so give the old bid back first
+        let raising = s.auctions.get(&id).and_then(|l| l.bidder.as_deref()) == Some(user);
+        release(s, id, now, if raising { "you raised it".to_string() } else { format!("outbid by {}", user) })?;
+
+        let acct = s.accounts.get_mut(user).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such account"))?;
+        if acct.balance < amount { return Err(BidError::Insufficient { need: amount, have: acct.balance }); }
+        acct.balance = acct.balance.minus(amount)?;
+        let balance = acct.balance;
+        let lot = s.auctions.entry(id).or_default();
+        lot.bidder = Some(user.to_string());
+        lot.bid = amount;
+        lot.bids += 1;
+        let leg = Leg::Corns { from: user_book(user), to: ESCROW.to_string(), amount };
+        s.record(Kind::Bid, now, format!("bid on auction #{} for {} x {}", id, auction.qty, item_name(snapshot, auction.item)), vec![leg]);
+        Ok(balance)
+    })
+}
+
+// A winner, the item they won and how many
+type Win = (String, Item, u64);
+
+/// Pays out and hands over every lot in `due` that isn't settled yet. Returns how many were
+/// settled and what each winner gets.
+fn settle_lots(s: &mut State, snapshot: &Snapshot, due: &[&Auction], now: u64) -> io::Result<(usize, Vec<Win>)> {
+    let (mut settled, mut won) = (0, Vec::new());
+    for a in due {
+        let lot = s.auctions.entry(a.id).or_default();
+        // Another settler may have got here since `due` was worked out
+        if lot.settled { continue; }
+        lot.settled = true;
+        settled += 1;
+        let Some(winner) = lot.bidder.clone() else { continue };
+        let amount = lot.bid;
+        let item = snapshot.items.iter().find(|it| it.id == a.item);
+        let Some(item) = item.filter(|it| s.stock_left(it).is_none_or(|left| left >= a.qty)) else {
+            release(s, a.id, now, "the item is no longer available".to_string()).map_err(io::Error::other)?;
+            continue;
+        };
+        s.take_stock(item, a.qty, now).map_err(|_| io::Error::other("stock changed while settling"))?;
+        let lot = s.auctions.entry(a.id).or_default();
+        lot.winner = lot.bidder.take();
+
+        let book = user_book(&winner);
+        let legs = vec![
+            Leg::Corns { from: ESCROW.to_string(), to: SHOP.to_string(), amount },
+            Leg::Item { id: item.id, qty: a.qty, from: SHOP.to_string(), to: book },
+        ];
+        let order = s.record(Kind::Auction, now, format!("won auction #{}: {} x {}", a.id, a.qty, item.name), legs);
+        if let Some(acct) = s.accounts.get_mut(&winner) {
+            acct.purchases.push(Purchase { order, time: now, item: item.id, qty: a.qty, total: amount, returned: 0, final_sale: true });
+            let owned = acct.owned.entry(item.id).or_insert(0);
+            *owned = owned.checked_add(a.qty).ok_or_else(|| io::Error::other(MoneyError::Overflow))?;
+        }
+        s.notify(&winner, now, format!("You won auction #{} (order #{}): {} x {} for {} corns.", a.id, order, a.qty, item.name, amount));
+        won.push((winner, item.clone(), a.qty));
+    }
+    Ok((settled, won))
+}
+
+/// Settles every auction whose deadline has passed: the winner pays out of escrow and gets the
+/// items, which are delivered into their inbox. Returns how many auctions were settled.
+pub fn settle_due(store: &Store, snapshot: &Snapshot, now: u64) -> io::Result<usize> {
+    let due: Vec<&Auction> = snapshot.auctions.iter()
+        .filter(|a| matches!(a.status(now), Status::Ended) && !store.read(|s| s.auctions.get(&a.id).is_some_and(|l| l.settled)))
+        .collect();
+    if due.is_empty() { return Ok(0); }
+
+    let (settled, won) = store.update(|s| settle_lots(s, snapshot, &due, now))?;
+
+    // Deliver after the sale is saved, the same way checkout does
+    for (winner, item, qty) in won {
+        let mut out = Vec::new();
+        let message = match item.delivery.fulfill(&Order { user: &winner, item: item.id, qty }, &mut out) {
+            Ok(()) => format!("Your {}:\n{}", item.name, String::from_utf8_lossy(&out).trim_end()),
+            Err(e) => {
+                eprintln!("fulfillment: item {} for {}: {}", item.id, winner, e);
+                format!("Delivery of {} failed, please contact an admin.", item.name)
+            }
+        };
+        store.update(|s| { s.notify(&winner, now, message); Ok::<_, io::Error>(()) })?;
+    }
+    Ok(settled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::{audit, ADMIN};
+    use crate::store::scratch;
+
+    const CATALOG: &str = r#"
+        [[item]]
+        id = 1
+        name = "One Clubby hair"
+        price = 5000
+        stock = 3
+        delivery = { kind = "text", text = "hair" }
+
+        [[auction]]
+        id = 1
+        item = 1
+        ends_at = 100
+    "#;
+
+    #[test]
+    fn a_lot_is_only_settled_once() {
+        let snapshot: Snapshot = toml::from_str(CATALOG).unwrap();
+        let store = scratch("settle-once");
+        store.register("alice", "hunter2").ok().unwrap();
+        store.update(|s| {
+            s.accounts.get_mut("alice").unwrap().balance = Corns::new(1000);
+            let leg = Leg::Corns { from: ADMIN.to_string(), to: user_book("alice"), amount: Corns::new(1000) };
+            s.record(Kind::Grant, 0, "grant".to_string(), vec![leg]);
+            Ok::<_, io::Error>(())
+        }).unwrap();
+        bid(&store, &snapshot, "alice", 1, Corns::new(600), 50).ok().unwrap();
+
+        // Two settlers that both saw the lot as due before either of them saved
+        let due: Vec<&Auction> = snapshot.auctions.iter().collect();
+        let first = store.update(|s| settle_lots(s, &snapshot, &due, 200)).unwrap();
+        let second = store.update(|s| settle_lots(s, &snapshot, &due, 200)).unwrap();
+        assert_eq!((first.0, first.1.len()), (1, 1));
+        assert_eq!((second.0, second.1.len()), (0, 0));
+        assert_eq!(settle_due(&store, &snapshot, 300).unwrap(), 0);
+
+        store.read(|s| {
+            assert_eq!(s.accounts["alice"].owned.get(&1), Some(&1));
+            assert_eq!(s.stock.get(&1), Some(&2));
+            let payouts = s.ledger.iter().filter(|tx| matches!(tx.kind, Kind::Auction)).count();
+            assert_eq!(payouts, 1);
+            let escrow = s.ledger.iter().map(|tx| tx.corns_for(ESCROW)).fold((0, 0), |(r, p), (a, b)| (r + a, p + b));
+            assert_eq!(escrow, (600, 600));
+            assert_eq!(audit(s), Vec::<String>::new());
+        });
+    }
+}
@@ -0,0 +1,23 @@
+use std::env;
+use std::process;
+
+use cor_shop::ledger::audit;
+use cor_shop::store::Store;
+
+// Replays the ledger in a store file and checks it against every balance, holding and stock level.
+fn main() {
+    let path = env::args().nth(1).or_else(|| env::var("CORSHOP_STORE").ok()).unwrap_or_else(|| "corshop.json".to_string());
+    // Only reads, so it can run next to a live shop
+    let state = Store::inspect(&path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        process::exit(2);
+    });
+    let (problems, transactions, accounts) = (audit(&state), state.ledger.len(), state.accounts.len());
+    if problems.is_empty() {
+        println!("{}: ok, {} transactions across {} accounts", path, transactions, accounts);
+        return;
+    }
+    for p in &problems { println!("{}", p); }
+    println!("{}: {} problems", path, problems.len());
+    process::exit(1);
+}
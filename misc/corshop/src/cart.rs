@@ -0,0 +1,30 @@
+use std::collections::BTreeMap;
+
+/// A session's shopping cart: item id -> quantity. Nothing is charged until checkout.
+#[derive(Default)]
+pub struct Cart { lines: BTreeMap<u32, u64> }
+
+impl Cart {
+    /// Adds `qty` units of `id`, returning the new quantity, or `None` if it would overflow.
+    pub fn add(&mut self, id: u32, qty: u64) -> Option<u64> {
+        let line = self.lines.entry(id).or_insert(0);
+        *line = line.checked_add(qty)?;
+        Some(*line)
+    }
+
+    /// Removes `qty` units of `id`, or the whole line if `qty` is `None` or covers it.
+    /// Returns what is left of the line, or `None` if the item was not in the cart.
+    pub fn remove(&mut self, id: u32, qty: Option<u64>) -> Option<u64> {
+        let line = self.lines.get_mut(&id)?;
+        match qty {
+            Some(qty) if qty < *line => { *line -= qty; Some(*line) }
+            _ => { self.lines.remove(&id); Some(0) }
+        }
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = (u32, u64)> + '_ { self.lines.iter().map(|(&id, &qty)| (id, qty)) }
+
+    pub fn is_empty(&self) -> bool { self.lines.is_empty() }
+
+    pub fn clear(&mut self) { self.lines.clear() }
+}
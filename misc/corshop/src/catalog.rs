@@ -0,0 +1,216 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use serde::Deserialize;
+use toml_edit::DocumentMut;
+
+use crate::admin::AdminConfig;
+use crate::auction::Auction;
+use crate::coupon::{Coupon, Discount};
+use crate::faucet::FaucetConfig;
+use crate::fulfillment::{self, Fulfillment};
+use crate::money::Corns;
+use crate::pricing::{Bundle, Tier};
+use crate::returns::ReturnsConfig;
+use crate::transfer::TransferConfig;
+
+// Anything pricier than this is almost certainly a typo in the catalog
+pub const MAX_PRICE: Corns = Corns::new(1_000_000_000);
+
+/// Default for the catalog's quantity fields.
+pub(crate) fn one() -> u64 { 1 }
+
+#[derive(Clone, Deserialize)]
+pub struct Item {
+    pub id: u32,
+    pub name: String,
+    pub price: Corns,
+    #[serde(default)]
+    pub description: String,
+    // Units available when the shop first opens, unlimited if left out. Changing it later adds or
+    // takes away the difference, see `State::reconcile_stock`
+    pub stock: Option<u64>,
+    // Cheaper unit prices for buying in bulk
+    #[serde(default)]
+    pub tiers: Vec<Tier>,
+    // Can't be refunded or sold back, for goods that are used up once the buyer has seen them
+    #[serde(default)]
+    pub final_sale: bool,
+    #[serde(deserialize_with = "fulfillment::deserialize")]
+    pub delivery: Arc<dyn Fulfillment>,
+}
+
+/// Everything read from the catalog file.
+#[derive(Deserialize)]
+pub struct Snapshot {
+    #[serde(rename = "item")]
+    pub items: Vec<Item>,
+    #[serde(default, rename = "coupon")]
+    pub coupons: Vec<Coupon>,
+    #[serde(default, rename = "bundle")]
+    pub bundles: Vec<Bundle>,
+    #[serde(default, rename = "auction")]
+    pub auctions: Vec<Auction>,
+    #[serde(default)]
+    pub faucet: FaucetConfig,
+    #[serde(default)]
+    pub returns: ReturnsConfig,
+    #[serde(default)]
+    pub transfers: TransferConfig,
+    #[serde(default)]
+    pub admin: AdminConfig,
+}
+
+impl Snapshot {
+    pub fn coupon(&self, code: &str) -> Option<&Coupon> { self.coupons.iter().find(|c| c.code == code) }
+
+    pub fn auction(&self, id: u32) -> Option<&Auction> { self.auctions.iter().find(|a| a.id == id) }
+}
+
+pub enum CatalogError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    Edit(toml_edit::TomlError),
+    NoSuchItem(u32),
+    NotEditable,
+    DuplicateId(u32),
+    BadId,
+    BadName(u32),
+    BadPrice(u32, Corns),
+    BadTier(u32, &'static str),
+    DuplicateBundle(u32),
+    BadBundle(u32, &'static str),
+    DuplicateCoupon(String),
+    BadCoupon(String, &'static str),
+    BadSellBack(u8),
+    DuplicateAuction(u32),
+    BadAuction(u32, &'static str),
+}
+
+impl fmt::Display for CatalogError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CatalogError::Io(e) => write!(f, "could not read catalog: {}", e),
+            CatalogError::Parse(e) => write!(f, "could not parse catalog: {}", e),
+            CatalogError::Edit(e) => write!(f, "could not edit catalog: {}", e),
+            CatalogError::NoSuchItem(id) => write!(f, "there is no item {}", id),
+            CatalogError::NotEditable => write!(f, "items must be written as [[item]] tables to be edited"),
+            CatalogError::DuplicateId(id) => write!(f, "item id {} is used more than once", id),
+            CatalogError::BadId => write!(f, "item id 0 is reserved"),
+            CatalogError::BadName(id) => write!(f, "item {} has an empty name", id),
+            CatalogError::BadPrice(id, price) => write!(f, "item {} has price {} above the maximum of {}", id, price, MAX_PRICE),
+            CatalogError::BadTier(id, why) => write!(f, "item {} has a price tier that {}", id, why),
+            CatalogError::DuplicateBundle(id) => write!(f, "bundle id {} is used more than once", id),
+            CatalogError::BadBundle(id, why) => write!(f, "bundle {} {}", id, why),
+            CatalogError::DuplicateCoupon(code) => write!(f, "coupon {} is defined more than once", code),
+            CatalogError::BadCoupon(code, why) => write!(f, "coupon {} {}", code, why),
+            CatalogError::DuplicateAuction(id) => write!(f, "auction id {} is used more than once", id),
+            CatalogError::BadAuction(id, why) => write!(f, "auction {} {}", id, why),
+            CatalogError::BadSellBack(pct) => write!(f, "sell_back_percent {} is above 100", pct),
+        }
+    }
+}
+
+impl From<CatalogError> for io::Error {
+    fn from(e: CatalogError) -> io::Error {
+        match e {
+            CatalogError::Io(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+        }
+    }
+}
+
+fn parse(src: &str) -> Result<Snapshot, CatalogError> {
+    let snapshot: Snapshot = toml::from_str(src).map_err(CatalogError::Parse)?;
+    let mut seen = HashSet::new();
+    for it in &snapshot.items {
+        if it.id == 0 { return Err(CatalogError::BadId); }
+        if !seen.insert(it.id) { return Err(CatalogError::DuplicateId(it.id)); }
+        if it.name.trim().is_empty() { return Err(CatalogError::BadName(it.id)); }
+        if it.price > MAX_PRICE { return Err(CatalogError::BadPrice(it.id, it.price)); }
+        let bad = |why| Err(CatalogError::BadTier(it.id, why));
+        let mut last = (1, it.price);
+        for t in &it.tiers {
+            if t.min <= last.0 { return bad("needs a minimum above 1 and above the tier before it"); }
+            if t.price > last.1 { return bad("costs more per unit than buying fewer"); }
+            last = (t.min, t.price);
+        }
+    }
+    let mut bundles = HashSet::new();
+    for b in &snapshot.bundles {
+        let bad = |why| Err(CatalogError::BadBundle(b.id, why));
+        if !bundles.insert(b.id) { return Err(CatalogError::DuplicateBundle(b.id)); }
+        if b.name.trim().is_empty() { return bad("has an empty name"); }
+        if b.price > MAX_PRICE { return bad("costs more than the maximum price"); }
+        if b.items.iter().any(|bi| !seen.contains(&bi.item)) { return bad("refers to an unknown item"); }
+        if b.items.iter().any(|bi| bi.qty == 0) { return bad("needs a quantity of at least 1 for each item"); }
+        if b.items.iter().enumerate().any(|(i, bi)| b.items[..i].iter().any(|o| o.item == bi.item)) { return bad("lists an item more than once"); }
+        if b.items.iter().fold(0u64, |n, bi| n.saturating_add(bi.qty)) < 2 { return bad("needs at least two units"); }
+    }
+    let mut codes = HashSet::new();
+    for c in &snapshot.coupons {
+        let bad = |why| Err(CatalogError::BadCoupon(c.code.clone(), why));
+        if c.code.is_empty() || c.code.contains(char::is_whitespace) { return bad("must be a single word"); }
+        if !codes.insert(c.code.as_str()) { return Err(CatalogError::DuplicateCoupon(c.code.clone())); }
+        match c.discount {
+            Discount::Percent(p) if p == 0 || p > 100 => return bad("needs a percentage between 1 and 100"),
+            Discount::Fixed(f) if f == Corns::ZERO || f > MAX_PRICE => return bad("needs a fixed discount between 1 and the maximum price"),
+            _ => {}
+        }
+        if c.items.iter().any(|id| !seen.contains(id)) { return bad("refers to an unknown item"); }
+    }
+    let mut auctions = HashSet::new();
+    for a in &snapshot.auctions {
+        let bad = |why| Err(CatalogError::BadAuction(a.id, why));
+        if !auctions.insert(a.id) { return Err(CatalogError::DuplicateAuction(a.id)); }
+        if !seen.contains(&a.item) { return bad("refers to an unknown item"); }
+        if a.qty == 0 { return bad("needs a quantity of at least 1"); }
+        if a.starts_at.is_some_and(|t| t >= a.ends_at) { return bad("ends before it starts"); }
+        if a.increment == Corns::ZERO { return bad("needs an increment of at least 1"); }
+    }
+    // Paying back more than was paid would mint corns
+    if snapshot.returns.sell_back_percent > 100 { return Err(CatalogError::BadSellBack(snapshot.returns.sell_back_percent)); }
+    Ok(snapshot)
+}
+
+/// The product catalog. Sessions take a snapshot per command, so a reload never disturbs a command in flight.
+pub struct Catalog { path: PathBuf, snapshot: RwLock<Arc<Snapshot>> }
+
+impl Catalog {
+    pub fn load(path: impl Into<PathBuf>) -> Result<Catalog, CatalogError> {
+        let path = path.into();
+        let snapshot = parse(&fs::read_to_string(&path).map_err(CatalogError::Io)?)?;
+        Ok(Catalog { path, snapshot: RwLock::new(Arc::new(snapshot)) })
+    }
+
+    /// Re-reads the catalog file. On error the current catalog is kept.
+    pub fn reload(&self) -> Result<usize, CatalogError> {
+        let snapshot = parse(&fs::read_to_string(&self.path).map_err(CatalogError::Io)?)?;
+        let n = snapshot.items.len();
+        *self.snapshot.write().unwrap() = Arc::new(snapshot);
+        Ok(n)
+    }
+
+    /// Changes the catalog file through `f` and switches to the result. Comments and layout in the
+    /// file are kept. Nothing is written unless the edited catalog is valid.
+    pub fn edit<T>(&self, f: impl FnOnce(&mut DocumentMut) -> Result<T, CatalogError>) -> Result<T, CatalogError> {
+        // Holding the write lock keeps two edits from racing on the file
+        let mut current = self.snapshot.write().unwrap();
+        let mut doc: DocumentMut = fs::read_to_string(&self.path).map_err(CatalogError::Io)?.parse().map_err(CatalogError::Edit)?;
+        let out = f(&mut doc)?;
+        let src = doc.to_string();
+        let snapshot = parse(&src)?;
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, src).and_then(|_| fs::rename(&tmp, &self.path)).map_err(CatalogError::Io)?;
+        *current = Arc::new(snapshot);
+        Ok(out)
+    }
+
+    pub fn snapshot(&self) -> Arc<Snapshot> { self.snapshot.read().unwrap().clone() }
+
+    pub fn get(&self, id: u32) -> Option<Item> { self.snapshot().items.iter().find(|it| it.id == id).cloned() }
+}
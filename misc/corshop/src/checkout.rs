@@ -0,0 +1,93 @@
+use std::fmt;
+use std::io;
+
+use crate::catalog::Item;
+use crate::coupon::{Coupon, CouponError};
+use crate::ledger::{user_book, Kind, Leg, SHOP};
+use crate::money::{Corns, MoneyError};
+use crate::pricing::{self, Bundle};
+use crate::store::{Purchase, Store};
+
+pub enum BuyError {
+    Insufficient { need: Corns, have: Corns },
+    OutOfStock { name: String, left: u64 },
+    UnknownItem(u32),
+    Coupon(CouponError),
+    Money(MoneyError),
+    Io(io::Error),
+}
+
+impl From<io::Error> for BuyError { fn from(e: io::Error) -> BuyError { BuyError::Io(e) } }
+
+impl From<MoneyError> for BuyError { fn from(e: MoneyError) -> BuyError { BuyError::Money(e) } }
+
+impl From<CouponError> for BuyError { fn from(e: CouponError) -> BuyError { BuyError::Coupon(e) } }
+
+impl fmt::Display for BuyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            // User should seriously invest in some corns
+            BuyError::Insufficient { need, have } => write!(f, "Insufficient balance. Need {}, have {}.", need, have),
+            BuyError::OutOfStock { name, left } => write!(f, "Out of stock. Only {} x {} left.", left, name),
+            BuyError::UnknownItem(id) => write!(f, "Item {} is no longer sold.", id),
+            BuyError::Coupon(e) => write!(f, "Can't use that coupon: {}.", e),
+            BuyError::Money(e) => write!(f, "Can't buy that: {}.", e),
+            BuyError::Io(_) => write!(f, "Could not save your purchase, try again later."),
+        }
+    }
+}
+
+pub struct ReceiptLine { pub item: Item, pub qty: u64, pub cost: Corns, pub discount: Corns }
+
+/// `order` is the id of the purchase in the ledger, which is also what `refund` takes.
+pub struct Receipt { pub order: u64, pub lines: Vec<ReceiptLine>, pub bundles: Vec<(Bundle, u64)>, pub discount: Corns, pub total: Corns }
+
+/// Charges `user` for every line in one transaction, so either all of them are bought or none are.
+/// Lines are priced with the cheapest mix of `bundles` and volume tiers, and a coupon is checked
+/// and counted inside the same transaction.
+pub fn purchase(store: &Store, user: &str, lines: &[(Item, u64)], bundles: &[Bundle], coupon: Option<&Coupon>, now: u64) -> Result<Receipt, BuyError> {
+    // Check stock first so an impossible quantity never gets as far as pricing
+    for (item, qty) in lines {
+        if let Some(left) = store.read(|s| s.stock_left(item)).filter(|left| left < qty) {
+            return Err(BuyError::OutOfStock { name: item.name.clone(), left });
+        }
+    }
+    let quote = pricing::quote(lines, bundles)?;
+    store.update(|s| {
+        let costs: Vec<(u32, Corns)> = lines.iter().map(|(it, _)| it.id).zip(quote.costs.iter().copied()).collect();
+        let discounts = match coupon {
+            Some(c) => { c.check(s, user, now)?; c.discounts(&costs)? }
+            None => vec![Corns::ZERO; lines.len()],
+        };
+
+        let mut receipt = Receipt { order: 0, lines: Vec::new(), bundles: quote.bundles.clone(), discount: Corns::ZERO, total: Corns::ZERO };
+        for ((item, qty), (&(_, cost), discount)) in lines.iter().zip(costs.iter().zip(discounts)) {
+            s.take_stock(item, *qty, now).map_err(|left| BuyError::OutOfStock { name: item.name.clone(), left })?;
+            receipt.total = receipt.total.plus(cost.minus(discount)?)?;
+            receipt.discount = receipt.discount.plus(discount)?;
+            receipt.lines.push(ReceiptLine { item: item.clone(), qty: *qty, cost, discount });
+        }
+        if let Some(c) = coupon { c.record_use(s, user)?; }
+
+        let book = user_book(user);
+        let mut legs = vec![Leg::Corns { from: book.clone(), to: SHOP.to_string(), amount: receipt.total }];
+        legs.extend(receipt.lines.iter().map(|l| Leg::Item { id: l.item.id, qty: l.qty, from: SHOP.to_string(), to: book.clone() }));
+        let mut memo = receipt.lines.iter().map(|l| format!("{} x {}", l.qty, l.item.name)).collect::<Vec<_>>().join(", ");
+        for (b, n) in &receipt.bundles { memo += &format!(" (bundle {} x {})", n, b.name); }
+        if let Some(c) = coupon { memo += &format!(" (coupon {})", c.code); }
+        receipt.order = s.record(Kind::Purchase, now, memo, legs);
+
+        let acct = s.accounts.get_mut(user).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such account"))?;
+        if acct.balance < receipt.total { return Err(BuyError::Insufficient { need: receipt.total, have: acct.balance }); }
+        acct.balance = acct.balance.minus(receipt.total)?;
+        for line in &receipt.lines {
+            acct.purchases.push(Purchase {
+                order: receipt.order, time: now, item: line.item.id, qty: line.qty, total: line.cost.minus(line.discount)?, returned: 0,
+                final_sale: line.item.final_sale,
+            });
+            let owned = acct.owned.entry(line.item.id).or_insert(0);
+            *owned = owned.checked_add(line.qty).ok_or(MoneyError::Overflow)?;
+        }
+        Ok(receipt)
+    })
+}
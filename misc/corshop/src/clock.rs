@@ -0,0 +1,31 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Set by `replay` so a transcript sees the same times on every run, 0 while the real clock is used
+static FIXED: AtomicU64 = AtomicU64::new(0);
+
+pub fn unix_now() -> u64 {
+    match FIXED.load(Ordering::Relaxed) {
+        0 => SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+        t => t,
+    }
+}
+
+/// Stops the clock at `t` for the rest of the process. Only for replays.
+pub fn set_now(t: u64) { FIXED.store(t.max(1), Ordering::Relaxed) }
+
+/// Formats a unix time as `YYYY-MM-DD HH:MM:SS` in UTC.
+pub fn format_utc(t: u64) -> String {
+    let (days, secs) = (t / 86_400, t % 86_400);
+    // Civil-from-days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, secs / 3_600, secs % 3_600 / 60, secs % 60)
+}
@@ -0,0 +1,94 @@
+use std::fmt;
+
+use serde::Deserialize;
+
+use crate::catalog::one;
+use crate::money::{Corns, MoneyError};
+use crate::store::State;
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Discount { Percent(u8), Fixed(Corns) }
+
+impl fmt::Display for Discount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Discount::Percent(p) => write!(f, "{}% off", p),
+            Discount::Fixed(c) => write!(f, "{} corns off", c),
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct Coupon {
+    pub code: String,
+    pub discount: Discount,
+    // Unix time after which the code stops working
+    pub expires_at: Option<u64>,
+    // Redemptions allowed across all users, unlimited if left out
+    pub max_uses: Option<u64>,
+    #[serde(default = "one")]
+    pub per_user: u64,
+    // Only these items are discounted, or every item if empty
+    #[serde(default)]
+    pub items: Vec<u32>,
+}
+
+#[derive(Debug)]
+pub enum CouponError { Unknown, Expired, UsedUp, AlreadyUsed, NotApplicable }
+
+impl fmt::Display for CouponError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CouponError::Unknown => write!(f, "no such coupon"),
+            CouponError::Expired => write!(f, "that coupon has expired"),
+            CouponError::UsedUp => write!(f, "that coupon has been used up"),
+            CouponError::AlreadyUsed => write!(f, "you have already used that coupon"),
+            CouponError::NotApplicable => write!(f, "that coupon does not apply to anything in this order"),
+        }
+    }
+}
+
+impl Coupon {
+    pub fn applies_to(&self, item: u32) -> bool { self.items.is_empty() || self.items.contains(&item) }
+
+    /// Checks that `user` may still use this code at unix time `now`.
+    pub fn check(&self, state: &State, user: &str, now: u64) -> Result<(), CouponError> {
+        if self.expires_at.is_some_and(|t| now >= t) { return Err(CouponError::Expired); }
+        if self.max_uses.is_some_and(|max| state.coupon_uses.get(&self.code).copied().unwrap_or(0) >= max) {
+            return Err(CouponError::UsedUp);
+        }
+        let used = state.accounts.get(user).and_then(|a| a.coupons.get(&self.code)).copied().unwrap_or(0);
+        if used >= self.per_user { return Err(CouponError::AlreadyUsed); }
+        Ok(())
+    }
+
+    /// The discount on each `(item, cost)` line. A line is never discounted by more than it costs,
+    /// and a fixed discount is spent on the lines in order.
+    pub fn discounts(&self, lines: &[(u32, Corns)]) -> Result<Vec<Corns>, CouponError> {
+        if !lines.iter().any(|&(id, _)| self.applies_to(id)) { return Err(CouponError::NotApplicable); }
+        let mut fixed_left = match self.discount { Discount::Fixed(c) => c, Discount::Percent(_) => Corns::ZERO };
+        Ok(lines.iter().map(|&(id, cost)| {
+            if !self.applies_to(id) { return Corns::ZERO; }
+            match self.discount {
+                Discount::Percent(p) => cost.percent(p),
+                Discount::Fixed(_) => {
+                    let off = cost.min(fixed_left);
+                    fixed_left = fixed_left.minus(off).unwrap_or(Corns::ZERO);
+                    off
+                }
+            }
+        }).collect())
+    }
+
+    /// Counts one use of this code by `user`.
+    pub fn record_use(&self, state: &mut State, user: &str) -> Result<(), MoneyError> {
+        let global = state.coupon_uses.entry(self.code.clone()).or_insert(0);
+        *global = global.checked_add(1).ok_or(MoneyError::Overflow)?;
+        if let Some(acct) = state.accounts.get_mut(user) {
+            let mine = acct.coupons.entry(self.code.clone()).or_insert(0);
+            *mine = mine.checked_add(1).ok_or(MoneyError::Overflow)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::money::Corns;
+use crate::rng;
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct FaucetConfig {
+    /// Corns credited per solved challenge.
+    pub reward: Corns,
+    /// Leading zero bits required for a user's first challenge.
+    pub base_difficulty: u32,
+    /// Extra bits required for every challenge the user has already solved.
+    pub step: u32,
+    pub max_difficulty: u32,
+}
+
+impl Default for FaucetConfig {
+    fn default() -> FaucetConfig { FaucetConfig { reward: Corns::new(10_000), base_difficulty: 20, step: 1, max_difficulty: 40 } }
+}
+
+impl FaucetConfig {
+    pub fn difficulty(&self, mined: u32) -> u32 {
+        mined.saturating_mul(self.step).saturating_add(self.base_difficulty).min(self.max_difficulty)
+    }
+}
+
+/// A hashcash-style challenge: find `x` such that sha256(prefix + x) starts with `difficulty` zero bits.
+pub struct Challenge { pub prefix: String, pub difficulty: u32 }
+
+impl Challenge {
+    pub fn new(difficulty: u32) -> Challenge {
+        let prefix = rng::bytes::<8>().iter().map(|b| format!("{:02x}", b)).collect();
+        Challenge { prefix, difficulty }
+    }
+
+    /// Number of leading zero bits in the hash of `answer`.
+    pub fn zero_bits(&self, answer: &str) -> u32 {
+        let hash = Sha256::new().chain_update(self.prefix.as_bytes()).chain_update(answer.as_bytes()).finalize();
+        let mut bits = 0;
+        for b in hash {
+            bits += b.leading_zeros();
+            if b != 0 { break; }
+        }
+        bits
+    }
+
+    pub fn solved_by(&self, answer: &str) -> bool { self.zero_bits(answer) >= self.difficulty }
+}
@@ -0,0 +1,89 @@
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::Arc;
+
+use serde::{Deserialize, Deserializer};
+
+/// What was bought and by whom, handed to a `Fulfillment` when delivering it.
+pub struct Order<'a> { pub user: &'a str, pub item: u32, pub qty: u64 }
+
+/// How an item gets into the buyer's hands once it has been paid for.
+pub trait Fulfillment: Send + Sync {
+    fn fulfill(&self, order: &Order, out: &mut dyn Write) -> io::Result<()>;
+}
+
+/// A fixed piece of text.
+pub struct Text(pub String);
+
+impl Fulfillment for Text {
+    fn fulfill(&self, _: &Order, out: &mut dyn Write) -> io::Result<()> { writeln!(out, "{}", self.0) }
+}
+
+/// The value of an environment variable, read at delivery time.
+pub struct EnvVar { pub var: String, pub fallback: String }
+
+impl Fulfillment for EnvVar {
+    fn fulfill(&self, _: &Order, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "{}", env::var(&self.var).unwrap_or_else(|_| self.fallback.clone()))
+    }
+}
+
+/// The contents of a file on disk, read at delivery time.
+pub struct File(pub PathBuf);
+
+impl Fulfillment for File {
+    fn fulfill(&self, _: &Order, out: &mut dyn Write) -> io::Result<()> {
+        out.write_all(&fs::read(&self.0)?)?;
+        out.flush()
+    }
+}
+
+/// The shop's own source code, embedded at build time.
+pub struct Source;
+
+impl Fulfillment for Source {
+    fn fulfill(&self, _: &Order, out: &mut dyn Write) -> io::Result<()> { writeln!(out, "{}", crate::SOURCE) }
+}
+
+/// The output of a local command. The order is passed in `CORSHOP_USER`, `CORSHOP_ITEM` and `CORSHOP_QTY`.
+pub struct Run { pub program: String, pub args: Vec<String> }
+
+impl Fulfillment for Run {
+    fn fulfill(&self, order: &Order, out: &mut dyn Write) -> io::Result<()> {
+        let output = Command::new(&self.program)
+            .args(&self.args)
+            .env("CORSHOP_USER", order.user)
+            .env("CORSHOP_ITEM", order.item.to_string())
+            .env("CORSHOP_QTY", order.qty.to_string())
+            .output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!("{} exited with {}", self.program, output.status)));
+        }
+        out.write_all(&output.stdout)?;
+        out.flush()
+    }
+}
+
+/// The `delivery` table of a catalog item.
+#[derive(Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Delivery {
+    Text { text: String },
+    Env { var: String, fallback: String },
+    File { path: PathBuf },
+    Source,
+    Command { program: String, #[serde(default)] args: Vec<String> },
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Arc<dyn Fulfillment>, D::Error> {
+    Ok(match Delivery::deserialize(d)? {
+        Delivery::Text { text } => Arc::new(Text(text)),
+        Delivery::Env { var, fallback } => Arc::new(EnvVar { var, fallback }),
+        Delivery::File { path } => Arc::new(File(path)),
+        Delivery::Source => Arc::new(Source),
+        Delivery::Command { program, args } => Arc::new(Run { program, args }),
+    })
+}
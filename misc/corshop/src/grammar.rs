@@ -0,0 +1,327 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use serde_json::Value;
+
+use crate::money::Corns;
+use crate::session::{AdminCommand, Command, Failure};
+
+/// How an argument is written on a text command line.
+pub enum Shape {
+    /// `<name>`, the next positional argument.
+    Required,
+    /// `[name]`, the next positional argument if there is one.
+    Optional,
+    /// `[--name <placeholder>]`, anywhere on the line.
+    Flag(&'static str),
+    /// `[name...]`, every positional argument left, joined by spaces.
+    Rest,
+}
+
+pub struct Param { pub name: &'static str, pub shape: Shape, pub about: &'static str }
+
+/// One command of the grammar. Text mode types `words` followed by the arguments; JSON mode sends
+/// `wire` as `cmd` (an admin command as `"cmd":"admin","action":...`) with the arguments as fields.
+pub struct Spec {
+    pub words: &'static str,
+    pub aliases: &'static [&'static str],
+    pub wire: &'static str,
+    pub params: &'static [Param],
+    pub summary: &'static str,
+}
+
+const fn param(name: &'static str, shape: Shape, about: &'static str) -> Param { Param { name, shape, about } }
+
+const fn spec(words: &'static str, wire: &'static str, params: &'static [Param], summary: &'static str) -> Spec {
+    Spec { words, aliases: &[], wire, params, summary }
+}
+
+const ITEM: Param = param("id", Shape::Required, "the item id from `list`");
+
+/// What customers can do, in the order `help` lists them.
+pub static COMMANDS: &[Spec] = &[
+    spec("list", "list", &[], "show products"),
+    spec("info", "info", &[ITEM], "show product details"),
+    spec("buy", "buy", &[
+        ITEM,
+        param("qty", Shape::Optional, "how many to buy, 1 if left out"),
+        param("coupon", Shape::Flag("code"), "a coupon code to use on this purchase"),
+    ], "attempt to purchase"),
+    spec("cart add", "cart_add", &[ITEM, param("qty", Shape::Optional, "how many to add, 1 if left out")], "put items in your cart"),
+    spec("cart remove", "cart_remove", &[ITEM, param("qty", Shape::Optional, "how many to take out, all of them if left out")], "take items out of your cart"),
+    Spec { aliases: &["cart"], ..spec("cart show", "cart_show", &[], "show your cart") },
+    spec("redeem", "redeem", &[param("code", Shape::Required, "the coupon code")], "apply a coupon at checkout"),
+    spec("checkout", "checkout", &[], "buy everything in your cart"),
+    spec("refund", "refund", &[param("order", Shape::Required, "the order number from your receipt")], "undo a recent order"),
+    spec("sell", "sell", &[ITEM, param("qty", Shape::Optional, "how many to sell, 1 if left out")], "sell items back to the shop"),
+    Spec { aliases: &["auction"], ..spec("auction list", "auctions", &[], "show auctions") },
+    spec("bid", "bid", &[
+        param("auction", Shape::Required, "the auction number from `auction list`"),
+        param("amount", Shape::Required, "corns to bid, held until the auction ends"),
+    ], "bid on an auction"),
+    spec("give", "give", &[param("user", Shape::Required, "who to send corns to"), param("amount", Shape::Required, "how many corns")], "send corns to another user"),
+    spec("gift", "gift", &[
+        param("user", Shape::Required, "who to send items to"),
+        ITEM,
+        param("qty", Shape::Optional, "how many to send, 1 if left out"),
+    ], "send items you own to another user"),
+    spec("balance", "balance", &[], "show your balance"),
+    spec("mine", "mine", &[param("answer", Shape::Optional, "a solution to your current challenge")], "earn corns with proof of work"),
+    spec("ledger", "ledger", &[], "show your transaction history"),
+    spec("inventory", "inventory", &[], "show what you own"),
+    spec("help", "help", &[param("command", Shape::Rest, "a command to explain")], "show this help"),
+    Spec { aliases: &["exit"], ..spec("quit", "quit", &[], "disconnect") },
+];
+
+/// What an unlocked admin console can do.
+pub static ADMIN: &[Spec] = &[
+    spec("admin restock", "admin:restock", &[ITEM, param("qty", Shape::Required, "units to add")], "add stock from the supplier"),
+    spec("admin price", "admin:price", &[ITEM, param("price", Shape::Required, "the new price in corns")], "change an item's price"),
+    spec("admin add", "admin:add", &[
+        ITEM,
+        param("price", Shape::Required, "the price in corns"),
+        param("name", Shape::Required, "the item's name, quoted if it has spaces"),
+        param("stock", Shape::Flag("n"), "units for sale, unlimited if left out"),
+        param("text", Shape::Flag("delivery"), "what buyers receive, the name if left out"),
+    ], "sell a new item"),
+    spec("admin remove", "admin:remove", &[ITEM], "stop selling an item"),
+    spec("admin grant", "admin:grant", &[param("user", Shape::Required, "who gets the corns"), param("amount", Shape::Required, "how many corns")], "give corns to a user"),
+    spec("admin sessions", "admin:sessions", &[], "show who is logged in"),
+];
+
+// Typed by the text front-end's prompts rather than at the `> ` prompt, so `help` leaves them out
+static HIDDEN: &[Spec] = &[
+    spec("login", "login", &[param("user", Shape::Required, "your username"), param("password", Shape::Required, "your password")], "log in"),
+    spec("register", "register", &[param("user", Shape::Required, "the username you want"), param("password", Shape::Required, "your new password")], "create an account and log in"),
+    spec("admin", "admin:unlock", &[param("token", Shape::Required, "the admin token")], "unlock the admin console"),
+];
+
+fn all() -> impl Iterator<Item = &'static Spec> { COMMANDS.iter().chain(ADMIN).chain(HIDDEN) }
+
+impl Spec {
+    /// How the command is typed, e.g. `buy <id> [qty] [--coupon <code>]`.
+    pub fn usage(&self) -> String {
+        let mut usage = self.words.to_string();
+        for p in self.params {
+            usage += &match p.shape {
+                Shape::Required => format!(" <{}>", p.name),
+                Shape::Optional => format!(" [{}]", p.name),
+                Shape::Flag(placeholder) => format!(" [--{} <{}>]", p.name, placeholder),
+                Shape::Rest => format!(" [{}...]", p.name),
+            };
+        }
+        usage
+    }
+
+    fn param(&self, name: &str) -> Option<&'static Param> { self.params.iter().find(|p| p.name == name) }
+}
+
+/// The command `words` name, as typed after `help`.
+pub fn find(words: &str) -> Option<&'static Spec> {
+    let words: Vec<&str> = words.split_whitespace().collect();
+    all().find(|s| s.words.split(' ').eq(words.iter().copied()) || s.aliases.iter().any(|a| a.split(' ').eq(words.iter().copied())))
+}
+
+fn usage_failure(code: &'static str, spec: &Spec, problem: String) -> Failure {
+    Failure::new(code, format!("{} Usage: {}", problem, spec.usage()))
+}
+
+/// The arguments of one command, checked against its spec but not yet converted.
+struct Args { spec: &'static Spec, values: BTreeMap<&'static str, String> }
+
+impl Args {
+    fn new(spec: &'static Spec, values: BTreeMap<&'static str, String>) -> Result<Args, Failure> {
+        let missing = spec.params.iter().find(|p| matches!(p.shape, Shape::Required) && !values.contains_key(p.name));
+        if let Some(p) = missing { return Err(usage_failure("missing_argument", spec, format!("Missing <{}>.", p.name))); }
+        Ok(Args { spec, values })
+    }
+
+    fn text(&self, name: &str) -> Option<String> { self.values.get(name).cloned() }
+
+    fn required(&self, name: &str) -> String { self.text(name).unwrap_or_default() }
+
+    fn number<T: FromStr>(&self, name: &str) -> Result<Option<T>, Failure> {
+        let Some(raw) = self.values.get(name) else { return Ok(None) };
+        raw.parse().map(Some).map_err(|_| {
+            let why = if !raw.is_empty() && raw.bytes().all(|b| b.is_ascii_digit()) { "is too large" } else { "must be a whole number" };
+            usage_failure("bad_argument", self.spec, format!("<{}> {}, got \"{}\".", name, why, raw))
+        })
+    }
+
+    fn num<T: FromStr + Default>(&self, name: &str) -> Result<T, Failure> { self.number(name).map(Option::unwrap_or_default) }
+
+    fn corns(&self, name: &str) -> Result<Corns, Failure> { self.num(name).map(Corns::new) }
+}
+
+/// Splits a line into words. Single or double quotes keep spaces in a word, and a backslash
+/// escapes the next character. Quoted words are never taken for `--flags`.
+fn tokenize(line: &str) -> Result<Vec<(String, bool)>, Failure> {
+    let mut words = Vec::new();
+    let mut chars = line.chars();
+    let mut word: Option<(String, bool)> = None;
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '"' | '\'' => {
+                let w = word.get_or_insert_with(Default::default);
+                w.1 = true;
+                loop {
+                    match chars.next() {
+                        Some(q) if q == c => break,
+                        Some('\\') if c == '"' => w.0.extend(chars.next()),
+                        Some(ch) => w.0.push(ch),
+                        None => return Err(Failure::new("bad_quote", format!("Missing closing {} quote.", c))),
+                    }
+                }
+            }
+            '\\' => word.get_or_insert_with(Default::default).0.extend(chars.next()),
+            c => word.get_or_insert_with(Default::default).0.push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Parses a command typed at the text prompt.
+pub fn parse_text(line: &str) -> Result<Command, Failure> {
+    let words = tokenize(line)?;
+    let typed = |w: &str| w.split(' ').count() <= words.len() && w.split(' ').zip(&words).all(|(a, (b, quoted))| !quoted && a == b);
+    let matched = all()
+        .flat_map(|s| std::iter::once(s.words).chain(s.aliases.iter().copied()).map(move |w| (s, w)))
+        .filter(|(_, w)| typed(w))
+        .max_by_key(|(_, w)| w.split(' ').count());
+    let Some((spec, w)) = matched else {
+        let first = words.first().map_or("", |(w, _)| w.as_str());
+        return Err(Failure::new("unknown_command", format!("Unknown command `{}`. Try `help`.", first)));
+    };
+
+    let mut values = BTreeMap::new();
+    let mut positional = spec.params.iter().filter(|p| !matches!(p.shape, Shape::Flag(_)));
+    let mut rest: Option<(&'static str, Vec<String>)> = None;
+    let mut args = words.into_iter().skip(w.split(' ').count());
+    while let Some((word, quoted)) = args.next() {
+        if let Some(flag) = word.strip_prefix("--").filter(|f| !quoted && !f.is_empty()) {
+            let p = spec.param(flag).filter(|p| matches!(p.shape, Shape::Flag(_)))
+                .ok_or_else(|| usage_failure("bad_argument", spec, format!("Unknown option --{}.", flag)))?;
+            let (value, _) = args.next().ok_or_else(|| usage_failure("missing_argument", spec, format!("--{} needs a value.", flag)))?;
+            values.insert(p.name, value);
+            continue;
+        }
+        if let Some((_, words)) = &mut rest { words.push(word); continue; }
+        match positional.next() {
+            Some(p) if matches!(p.shape, Shape::Rest) => rest = Some((p.name, vec![word])),
+            Some(p) => { values.insert(p.name, word); }
+            None => return Err(usage_failure("bad_argument", spec, format!("Unexpected argument \"{}\".", word))),
+        }
+    }
+    if let Some((name, words)) = rest { values.insert(name, words.join(" ")); }
+    build(Args::new(spec, values)?)
+}
+
+/// Parses one line of the JSON protocol, e.g. `{"cmd":"buy","id":3,"qty":2}`.
+pub fn parse_json(line: &str) -> Result<Command, Failure> {
+    let v: Value = serde_json::from_str(line).map_err(|e| Failure::new("bad_request", e.to_string()))?;
+    let Value::Object(mut fields) = v else { return Err(Failure::new("bad_request", "Send one JSON object per line.")) };
+    let wire = match (fields.remove("cmd"), fields.remove("action")) {
+        (Some(Value::String(cmd)), None) => cmd,
+        (Some(Value::String(cmd)), Some(Value::String(action))) if cmd == "admin" => format!("admin:{}", action),
+        (Some(Value::String(_)), Some(_)) => return Err(Failure::new("bad_request", "Only `admin` takes an `action`, and it must be a string.")),
+        _ => return Err(Failure::new("bad_request", "Missing `cmd`, which must be a string.")),
+    };
+    let spec = all().find(|s| s.wire == wire)
+        .ok_or_else(|| Failure::new("unknown_command", format!("Unknown command `{}`. Send {{\"cmd\":\"help\"}}.", wire.replace(':', " "))))?;
+
+    let mut values = BTreeMap::new();
+    for (name, value) in fields {
+        let p = spec.param(&name).ok_or_else(|| usage_failure("bad_argument", spec, format!("Unexpected field `{}`.", name)))?;
+        match value {
+            Value::Null => {}
+            Value::String(s) => { values.insert(p.name, s); }
+            Value::Number(n) => { values.insert(p.name, n.to_string()); }
+            _ => return Err(usage_failure("bad_argument", spec, format!("<{}> must be a string or a number.", name))),
+        }
+    }
+    build(Args::new(spec, values)?)
+}
+
+fn build(a: Args) -> Result<Command, Failure> {
+    let qty = |a: &Args| a.number("qty").map(|q| q.unwrap_or(1));
+    Ok(match a.spec.wire {
+        "login" => Command::Login { user: a.required("user"), password: a.required("password") },
+        "register" => Command::Register { user: a.required("user"), password: a.required("password") },
+        "list" => Command::List,
+        "info" => Command::Info { id: a.num("id")? },
+        "buy" => Command::Buy { id: a.num("id")?, qty: qty(&a)?, coupon: a.text("coupon") },
+        "cart_add" => Command::CartAdd { id: a.num("id")?, qty: qty(&a)? },
+        "cart_remove" => Command::CartRemove { id: a.num("id")?, qty: a.number("qty")? },
+        "cart_show" => Command::CartShow,
+        "redeem" => Command::Redeem { code: a.required("code") },
+        "checkout" => Command::Checkout,
+        "refund" => Command::Refund { order: a.num("order")? },
+        "sell" => Command::Sell { id: a.num("id")?, qty: qty(&a)? },
+        "auctions" => Command::Auctions,
+        "bid" => Command::Bid { auction: a.num("auction")?, amount: a.corns("amount")? },
+        "give" => Command::Give { user: a.required("user"), amount: a.corns("amount")? },
+        "gift" => Command::Gift { user: a.required("user"), id: a.num("id")?, qty: qty(&a)? },
+        "balance" => Command::Balance,
+        "mine" => Command::Mine { answer: a.text("answer") },
+        "ledger" => Command::Ledger,
+        "inventory" => Command::Inventory,
+        "help" => Command::Help { command: a.text("command") },
+        "quit" => Command::Quit,
+        "admin:unlock" => Command::Admin(AdminCommand::Unlock { token: a.required("token") }),
+        "admin:restock" => Command::Admin(AdminCommand::Restock { id: a.num("id")?, qty: a.num("qty")? }),
+        "admin:price" => Command::Admin(AdminCommand::Price { id: a.num("id")?, price: a.corns("price")? }),
+        "admin:add" => Command::Admin(AdminCommand::Add {
+            id: a.num("id")?, name: a.required("name"), price: a.corns("price")?, stock: a.number("stock")?, text: a.text("text"),
+        }),
+        "admin:remove" => Command::Admin(AdminCommand::Remove { id: a.num("id")? }),
+        "admin:grant" => Command::Admin(AdminCommand::Grant { user: a.required("user"), amount: a.corns("amount")? }),
+        "admin:sessions" => Command::Admin(AdminCommand::Sessions),
+        // A spec added without a case here is a bug, but not one worth taking the session down for
+        wire => return Err(Failure::new("unknown_command", format!("`{}` isn't available yet.", wire.replace(':', " ")))),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(line: &str) -> Vec<(String, bool)> { tokenize(line).ok().unwrap_or_default() }
+
+    fn failure(result: Result<Command, Failure>) -> (&'static str, String) {
+        match result { Ok(_) => panic!("expected a failure"), Err(f) => (f.code, f.message) }
+    }
+
+    #[test]
+    fn quotes_keep_spaces_and_are_marked() {
+        let w = |s: &str, q| (s.to_string(), q);
+        assert_eq!(words("  gift  'bo b' 1 "), [w("gift", false), w("bo b", true), w("1", false)]);
+        assert_eq!(words(r#"a"b c"d"#), [w("ab cd", true)]);
+        assert_eq!(words(r#"'' """#), [w("", true), w("", true)]);
+    }
+
+    #[test]
+    fn backslashes_escape_outside_and_in_double_quotes_only() {
+        assert_eq!(words(r#"a\ b "x\"y" 'p\q'"#).into_iter().map(|(w, _)| w).collect::<Vec<_>>(), [r"a b", r#"x"y"#, r"p\q"]);
+        assert!(matches!(tokenize("say 'oops"), Err(f) if f.code == "bad_quote"));
+    }
+
+    #[test]
+    fn a_quoted_word_is_never_a_command_or_flag() {
+        assert!(matches!(parse_text("buy 1 2 '--coupon'"), Err(f) if f.message.starts_with("Unexpected argument \"--coupon\"")));
+        assert!(matches!(parse_text("'list'"), Err(f) if f.code == "unknown_command"));
+        assert!(matches!(parse_text("buy 1 --coupon 'SPRING 10'"), Ok(Command::Buy { coupon: Some(c), .. }) if c == "SPRING 10"));
+    }
+
+    #[test]
+    fn each_argument_problem_has_its_own_message() {
+        assert_eq!(failure(parse_text("buy")), ("missing_argument", "Missing <id>. Usage: buy <id> [qty] [--coupon <code>]".to_string()));
+        assert_eq!(failure(parse_text("buy x")).1, "<id> must be a whole number, got \"x\". Usage: buy <id> [qty] [--coupon <code>]");
+        assert_eq!(failure(parse_text("buy 99999999999")).1, "<id> is too large, got \"99999999999\". Usage: buy <id> [qty] [--coupon <code>]");
+        assert_eq!(failure(parse_text("buy 1 --coupon")).0, "missing_argument");
+        assert!(failure(parse_text("buy 1 --nope x")).1.starts_with("Unknown option --nope."));
+        assert!(failure(parse_json(r#"{"cmd":"buy","id":1,"colour":"red"}"#)).1.starts_with("Unexpected field `colour`."));
+        assert!(failure(parse_json(r#"{"cmd":"buy","id":[1]}"#)).1.starts_with("<id> must be a string or a number."));
+    }
+}
@@ -0,0 +1,42 @@
+use std::io::{BufRead, Write};
+
+use serde_json::{json, Value};
+
+use crate::catalog::Catalog;
+use crate::grammar;
+use crate::limits::{Limits, Lines};
+use crate::session::{Reply, Session};
+use crate::store::Store;
+
+/// The shop for scripts: one JSON command per line in, one JSON object per line out.
+/// Replies look like `{"ok":true,"reply":"bought",...}`, failures like `{"ok":false,"error":"out_of_stock","message":"..."}`.
+pub fn shop<R: BufRead, W: Write>(reader: R, mut writer: W, store: &Store, catalog: &Catalog, limits: Limits) {
+    let mut session = Session::new(store, catalog);
+    let mut lines = Lines::new(reader, limits);
+    loop {
+        let line = match lines.line() {
+            Ok(line) => line,
+            Err(cutoff) => {
+                // Say why before hanging up, unless the client is already gone
+                if let Some(code) = cutoff.code() {
+                    let _ = writeln!(writer, "{}", json!({ "ok": false, "error": code, "message": cutoff.to_string() }));
+                    let _ = writer.flush();
+                }
+                break;
+            }
+        };
+        if line.is_empty() { continue; }
+
+        let (out, quit) = match grammar::parse_json(&line).and_then(|cmd| session.execute(cmd)) {
+            Ok(reply) => {
+                let mut v = serde_json::to_value(&reply).unwrap_or(Value::Null);
+                if let Value::Object(fields) = &mut v { fields.insert("ok".to_string(), Value::Bool(true)); }
+                (v, matches!(reply, Reply::Bye))
+            }
+            Err(f) => (json!({ "ok": false, "error": f.code, "message": f.message }), false),
+        };
+        let _ = writeln!(writer, "{}", out);
+        let _ = writer.flush();
+        if quit { break; }
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::money::Corns;
+use crate::store::State;
+
+// Books that belong to the shop rather than to a user
+pub const SHOP: &str = "shop";
+pub const FAUCET: &str = "faucet";
+pub const SUPPLIER: &str = "supplier";
+pub const ADMIN: &str = "admin";
+// Holds the high bid on every open auction
+pub const ESCROW: &str = "escrow";
+// Where everything a store held before it kept a ledger came from
+pub const OPENING: &str = "opening";
+
+pub fn user_book(user: &str) -> String { format!("user:{}", user) }
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind { Purchase, Refund, Sell, Faucet, Grant, Give, Gift, Bid, Release, Auction, Stock, Opening }
+
+/// One movement between two books. Whatever leaves `from` arrives at `to`, so every leg is
+/// balanced on its own and so is any transaction made of them.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "asset", rename_all = "snake_case")]
+pub enum Leg {
+    Corns { from: String, to: String, amount: Corns },
+    Item { id: u32, qty: u64, from: String, to: String },
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Transaction { pub id: u64, pub time: u64, pub kind: Kind, pub memo: String, pub legs: Vec<Leg> }
+
+impl Transaction {
+    /// Corns this transaction moved into and out of `book`.
+    pub fn corns_for(&self, book: &str) -> (u128, u128) {
+        let (mut received, mut paid) = (0, 0);
+        for leg in &self.legs {
+            if let Leg::Corns { from, to, amount } = leg {
+                if to == book { received += amount.get() as u128; }
+                if from == book { paid += amount.get() as u128; }
+            }
+        }
+        (received, paid)
+    }
+}
+
+/// Every movement of corns and stock, oldest first. The saved history is shared between copies, so
+/// cloning the state for an update doesn't copy it.
+#[derive(Clone, Default)]
+pub struct Ledger { saved: Arc<Vec<Transaction>>, pending: Vec<Transaction> }
+
+impl Ledger {
+    pub fn len(&self) -> usize { self.saved.len() + self.pending.len() }
+
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Transaction> { self.saved.iter().chain(&self.pending) }
+
+    pub(crate) fn push(&mut self, tx: Transaction) { self.pending.push(tx) }
+
+    /// Transactions added since the ledger was last saved.
+    pub(crate) fn pending(&self) -> &[Transaction] { &self.pending }
+
+    /// Marks the pending transactions as saved. Only copies the history if another clone still shares it.
+    pub(crate) fn commit(&mut self) { Arc::make_mut(&mut self.saved).append(&mut self.pending) }
+}
+
+impl From<Vec<Transaction>> for Ledger {
+    fn from(saved: Vec<Transaction>) -> Ledger { Ledger { saved: Arc::new(saved), pending: Vec::new() } }
+}
+
+/// Replays the whole ledger from empty books and compares the result with the balances, holdings
+/// and stock levels in `state`. Returns one line per discrepancy.
+pub fn audit(state: &State) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut corns: BTreeMap<&str, i128> = BTreeMap::new();
+    let mut goods: BTreeMap<(&str, u32), i128> = BTreeMap::new();
+
+    for (i, tx) in state.ledger.iter().enumerate() {
+        if tx.id != i as u64 + 1 { problems.push(format!("transaction #{} is out of sequence at position {}", tx.id, i + 1)); }
+        for leg in &tx.legs {
+            match leg {
+                Leg::Corns { from, to, amount } => {
+                    *corns.entry(from).or_default() -= amount.get() as i128;
+                    *corns.entry(to).or_default() += amount.get() as i128;
+                }
+                Leg::Item { id, qty, from, to } => {
+                    *goods.entry((from, *id)).or_default() -= *qty as i128;
+                    *goods.entry((to, *id)).or_default() += *qty as i128;
+                }
+            }
+        }
+        // Users can never be overdrawn, not even halfway through the history
+        for leg in &tx.legs {
+            let Leg::Corns { from, .. } = leg else { continue };
+            let n = corns[from.as_str()];
+            if from.starts_with("user:") && n < 0 { problems.push(format!("transaction #{} leaves {} at {} corns", tx.id, from, n)); }
+        }
+    }
+
+    for (name, acct) in &state.accounts {
+        let book = user_book(name);
+        let replayed = corns.get(book.as_str()).copied().unwrap_or(0);
+        if replayed != acct.balance.get() as i128 {
+            problems.push(format!("{} has balance {} but the ledger says {}", book, acct.balance, replayed));
+        }
+        let held = goods.iter().filter(|((b, _), n)| *b == book && **n != 0).map(|((_, id), n)| (*id, *n));
+        let owned = acct.owned.iter().filter(|(_, q)| **q != 0).map(|(id, q)| (*id, *q as i128));
+        if !held.clone().eq(owned) {
+            problems.push(format!("{} owns {:?} but the ledger says {:?}", book, acct.owned, held.collect::<BTreeMap<_, _>>()));
+        }
+    }
+    let books: BTreeSet<&str> = corns.keys().chain(goods.keys().map(|(b, _)| b)).copied().collect();
+    for book in books {
+        if let Some(name) = book.strip_prefix("user:") {
+            if !state.accounts.contains_key(name) { problems.push(format!("ledger mentions {} which has no account", book)); }
+        }
+    }
+    let held: i128 = state.auctions.values().filter(|l| !l.settled && l.bidder.is_some()).map(|l| l.bid.get() as i128).sum();
+    let escrow = corns.get(ESCROW).copied().unwrap_or(0);
+    if escrow != held { problems.push(format!("open auctions hold {} in bids but the ledger has {} in escrow", held, escrow)); }
+    for (id, left) in &state.stock {
+        let replayed = goods.get(&(SHOP, *id)).copied().unwrap_or(0);
+        if replayed != *left as i128 {
+            problems.push(format!("item {} has {} in stock but the ledger says {}", id, left, replayed));
+        }
+    }
+    problems
+}
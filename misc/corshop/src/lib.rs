@@ -0,0 +1,47 @@
+use std::io::{BufRead, Write};
+
+pub mod admin;
+pub mod auction;
+pub mod cart;
+pub mod catalog;
+pub mod checkout;
+pub mod clock;
+pub mod coupon;
+pub mod faucet;
+pub mod fulfillment;
+pub mod grammar;
+pub mod json;
+pub mod ledger;
+pub mod limits;
+pub mod money;
+pub mod pricing;
+pub mod replay;
+pub mod returns;
+pub mod rng;
+pub mod server;
+pub mod session;
+pub mod store;
+pub mod text;
+pub mod transfer;
+#[cfg(feature = "tui")]
+pub mod tui;
+
+use catalog::Catalog;
+use limits::Limits;
+use store::Store;
+
+// What the "cor.shop's source code" item hands out: every file under src/, put together by build.rs
+pub const SOURCE: &str = include_str!(concat!(env!("OUT_DIR"), "/source.txt"));
+
+/// How a session talks to its client.
+#[derive(Clone, Copy)]
+pub enum Protocol { Text, Json }
+
+impl Protocol {
+    pub fn run<R: BufRead, W: Write>(self, reader: R, writer: W, store: &Store, catalog: &Catalog, limits: Limits) {
+        match self {
+            Protocol::Text => text::shop(reader, writer, store, catalog, limits),
+            Protocol::Json => json::shop(reader, writer, store, catalog, limits),
+        }
+    }
+}
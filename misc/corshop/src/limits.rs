@@ -0,0 +1,143 @@
+use std::fmt;
+use std::io::{self, BufRead};
+use std::time::{Duration, Instant};
+
+/// How much a client may send before it is disconnected. `None` means no limit.
+#[derive(Clone, Copy, Default)]
+pub struct Limits {
+    // Longest line accepted, in bytes, newline included
+    pub max_line: Option<usize>,
+    pub rate: Option<Rate>,
+    // Both need the reader to time out or be hung up on, `Lines` only reports why
+    pub idle_timeout: Option<Duration>,
+    pub max_session: Option<Duration>,
+}
+
+/// A token bucket: `burst` lines at once, refilled at `per_second`.
+#[derive(Clone, Copy)]
+pub struct Rate { pub per_second: f64, pub burst: f64 }
+
+/// Why a session stopped reading.
+pub enum Cutoff { Closed, TooLong(usize), TooFast, Idle(Duration), SessionOver(Duration) }
+
+impl Cutoff {
+    /// Stable code for the JSON protocol, `None` when the client simply went away.
+    pub fn code(&self) -> Option<&'static str> {
+        match self {
+            Cutoff::Closed => None,
+            Cutoff::TooLong(_) => Some("line_too_long"),
+            Cutoff::TooFast => Some("rate_limited"),
+            Cutoff::Idle(_) => Some("idle_timeout"),
+            Cutoff::SessionOver(_) => Some("session_time_limit"),
+        }
+    }
+}
+
+impl fmt::Display for Cutoff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Cutoff::Closed => write!(f, "Connection closed."),
+            Cutoff::TooLong(max) => write!(f, "Lines can be at most {} bytes. Disconnecting.", max),
+            Cutoff::TooFast => write!(f, "Too many commands, slow down. Disconnecting."),
+            Cutoff::Idle(t) => write!(f, "No input for {} seconds. Disconnecting.", t.as_secs()),
+            Cutoff::SessionOver(t) => write!(f, "Sessions last at most {} seconds. Disconnecting.", t.as_secs()),
+        }
+    }
+}
+
+/// Reads a client's lines while enforcing `Limits`.
+pub struct Lines<R> {
+    reader: R,
+    limits: Limits,
+    started: Instant,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl<R: BufRead> Lines<R> {
+    pub fn new(reader: R, limits: Limits) -> Lines<R> {
+        let now = Instant::now();
+        Lines { reader, limits, started: now, tokens: limits.rate.map_or(0.0, |r| r.burst), refilled: now }
+    }
+
+    /// The next line, trimmed.
+    pub fn line(&mut self) -> Result<String, Cutoff> {
+        let mut line = Vec::new();
+        let done = self.read_line(&mut line);
+        // A session that ran out of time gets hung up on, which looks like an ordinary close here
+        if let Some(max) = self.limits.max_session.filter(|&max| self.started.elapsed() >= max) {
+            return Err(Cutoff::SessionOver(max));
+        }
+        done?;
+        self.spend()?;
+        Ok(String::from_utf8_lossy(&line).trim().to_string())
+    }
+
+    fn read_line(&mut self, line: &mut Vec<u8>) -> Result<(), Cutoff> {
+        let max = self.limits.max_line.unwrap_or(usize::MAX);
+        loop {
+            let buf = match self.reader.fill_buf() {
+                Ok(buf) => buf,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                    return Err(self.limits.idle_timeout.map_or(Cutoff::Closed, Cutoff::Idle));
+                }
+                Err(_) => return Err(Cutoff::Closed),
+            };
+            if buf.is_empty() { return if line.is_empty() { Err(Cutoff::Closed) } else { Ok(()) }; }
+            let (take, end) = match buf.iter().position(|&b| b == b'\n') {
+                Some(i) => (i + 1, true),
+                None => (buf.len(), false),
+            };
+            if line.len() + take > max { return Err(Cutoff::TooLong(max)); }
+            line.extend_from_slice(&buf[..take]);
+            self.reader.consume(take);
+            if end { return Ok(()); }
+        }
+    }
+
+    // Takes a token for one line, refilling the bucket for the time since the last one
+    fn spend(&mut self) -> Result<(), Cutoff> {
+        let Some(rate) = self.limits.rate else { return Ok(()) };
+        let now = Instant::now();
+        self.tokens = (self.tokens + now.duration_since(self.refilled).as_secs_f64() * rate.per_second).min(rate.burst);
+        self.refilled = now;
+        if self.tokens < 1.0 { return Err(Cutoff::TooFast); }
+        self.tokens -= 1.0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn lines(input: &str, limits: Limits) -> Lines<Cursor<Vec<u8>>> { Lines::new(Cursor::new(input.as_bytes().to_vec()), limits) }
+
+    #[test]
+    fn a_line_over_the_limit_is_cut_off() {
+        let mut l = lines("abcd\nabcdef\n", Limits { max_line: Some(5), ..Limits::default() });
+        assert_eq!(l.line().ok().as_deref(), Some("abcd"));
+        assert!(matches!(l.line(), Err(Cutoff::TooLong(5))));
+    }
+
+    #[test]
+    fn the_bucket_allows_a_burst_then_refills() {
+        let mut l = lines("a\nb\nc\nd\n", Limits { rate: Some(Rate { per_second: 1.0, burst: 2.0 }), ..Limits::default() });
+        assert!(l.line().is_ok() && l.line().is_ok());
+        assert!(matches!(l.line(), Err(Cutoff::TooFast)));
+        // A second later there is one token again
+        l.refilled -= Duration::from_secs(1);
+        assert_eq!(l.line().ok().as_deref(), Some("d"));
+    }
+
+    #[test]
+    fn a_session_past_its_time_is_over() {
+        let mut l = lines("a\n", Limits { max_session: Some(Duration::ZERO), ..Limits::default() });
+        assert!(matches!(l.line(), Err(Cutoff::SessionOver(_))));
+        let mut l = lines("a\n", Limits { max_session: Some(Duration::from_secs(60)), ..Limits::default() });
+        assert_eq!(l.line().ok().as_deref(), Some("a"));
+        assert!(matches!(l.line(), Err(Cutoff::Closed)));
+    }
+}
@@ -1,139 +1,192 @@
 use std::env;
-use std::io::{self, BufRead, BufReader, Write};
+use std::fs;
+use std::io::{self, BufReader};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
-#[derive(Clone, Copy)]
-struct Item { id: u32, name: &'static str, price: u64 }
+use signal_hook::consts::SIGHUP;
+use signal_hook::iterator::Signals;
 
-fn items() -> Vec<Item> { vec![
-    Item { id: 1, name: "FizzBuzz101's tears", price: 250_000 },
-    Item { id: 2, name: "One Clubby hair", price: 400_000 },
-    Item { id: 3, name: "Day's Heap", price: 600_000 },
-    Item { id: 4, name: "cor.shop's source code", price: 0}
-]}
+use cor_shop::auction;
+use cor_shop::catalog::Catalog;
+use cor_shop::clock::unix_now;
+use cor_shop::limits::{Limits, Rate};
+use cor_shop::replay;
+use cor_shop::rng;
+use cor_shop::server::{self, ServerConfig};
+use cor_shop::store::{self, Store};
+use cor_shop::Protocol;
 
-fn banner() -> &'static str { r#"=====================================
-         Welcome to cor.shop 
-=====================================
-Commands:
-  list                 - show products
-  buy <id> <qty>       - attempt to purchase
-  balance              - show your balance
-  help                 - show this help
-  quit                 - disconnect
+const USAGE: &str = "usage: cor-shop [--json | --tui] [--listen <addr>] [--max-sessions <n>] [--idle-timeout <secs>]
+                [--max-session-time <secs>] [--max-line <bytes>] [--rate <commands/sec>] [--burst <commands>]
+       cor-shop replay <transcript> [--json] [--seed <n>] [--clock <unix time>]
+Setting a limit to 0 turns it off. A replay starts from an empty store and prints the session.";
 
-"# }
+struct Options {
+    protocol: Protocol,
+    tui: bool,
+    listen: Option<String>,
+    max_sessions: usize,
+    idle_timeout: u64,
+    max_session_time: u64,
+    max_line: usize,
+    rate: f64,
+    burst: u32,
+    replay: Option<String>,
+    seed: u64,
+    clock: u64,
+}
 
-const SOURCE: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/", file!()));
+impl Options {
+    fn limits(&self) -> Limits {
+        let secs = |s| Some(Duration::from_secs(s)).filter(|_| s > 0);
+        Limits {
+            max_line: Some(self.max_line).filter(|&n| n > 0),
+            rate: Some(Rate { per_second: self.rate, burst: self.burst as f64 }).filter(|_| self.rate > 0.0 && self.burst > 0),
+            idle_timeout: secs(self.idle_timeout),
+            max_session: secs(self.max_session_time),
+        }
+    }
+}
 
-fn shop<R: BufRead, W: Write>(mut reader: R, mut writer: W, heap: &str) {
-    let mut balance: u64 = 0;
-    let _ = writeln!(writer, "{}", banner());
-    let _ = writeln!(writer, "Balance: {} corns", balance);
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut opts = Options {
+        protocol: Protocol::Text, tui: false, listen: None, max_sessions: 64,
+        idle_timeout: 300, max_session_time: 1800, max_line: 4096, rate: 10.0, burst: 30,
+        replay: None, seed: 0, clock: 1_700_000_000,
+    };
+    // The first option that only means something to a replay
+    let mut replay_only = None;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--json" => opts.protocol = Protocol::Json,
+            "--tui" if cfg!(feature = "tui") => opts.tui = true,
+            "--tui" => return Err("--tui needs a build with `--features tui`".to_string()),
+            "--listen" => opts.listen = Some(value()?),
+            "--max-sessions" => opts.max_sessions = value()?.parse().map_err(|_| "--max-sessions must be a number")?,
+            "--idle-timeout" => opts.idle_timeout = value()?.parse().map_err(|_| "--idle-timeout must be a number of seconds")?,
+            "--max-session-time" => opts.max_session_time = value()?.parse().map_err(|_| "--max-session-time must be a number of seconds")?,
+            "--max-line" => opts.max_line = value()?.parse().map_err(|_| "--max-line must be a number of bytes")?,
+            "--rate" => opts.rate = value()?.parse().ok().filter(|r: &f64| r.is_finite() && *r >= 0.0).ok_or("--rate must be a number of commands per second")?,
+            "--burst" => opts.burst = value()?.parse().map_err(|_| "--burst must be a number")?,
+            "replay" => opts.replay = Some(value()?),
+            "--seed" => {
+                opts.seed = value()?.parse().map_err(|_| "--seed must be a number")?;
+                replay_only.get_or_insert("--seed");
+            }
+            "--clock" => {
+                opts.clock = value()?.parse().ok().filter(|&t| t > 0).ok_or("--clock must be a unix time")?;
+                replay_only.get_or_insert("--clock");
+            }
+            _ => return Err(format!("unknown argument {}", arg)),
+        }
+    }
+    // The terminal UI runs on the local terminal only
+    if opts.tui && (opts.listen.is_some() || matches!(opts.protocol, Protocol::Json)) {
+        return Err("--tui can't be combined with --json or --listen".to_string());
+    }
+    if opts.replay.is_some() && (opts.tui || opts.listen.is_some()) {
+        return Err("replay can't be combined with --tui or --listen".to_string());
+    }
+    if let Some(flag) = replay_only.filter(|_| opts.replay.is_none()) {
+        return Err(format!("{} only applies to replay", flag));
+    }
+    Ok(opts)
+}
 
-    loop {
-        // Print prompt
-        let _ = write!(writer, "> ");
-        let _ = writer.flush();
+// Stdin can't time out, so the session reads it through a socket that can. Hanging up the socket
+// ends the session the same way it does for a TCP client.
+fn timed_stdin(limits: &Limits) -> io::Result<UnixStream> {
+    let (mut tx, rx) = UnixStream::pair()?;
+    thread::spawn(move || { let _ = io::copy(&mut io::stdin().lock(), &mut tx); });
+    rx.set_read_timeout(limits.idle_timeout)?;
+    if let Some(max) = limits.max_session {
+        let hang_up = rx.try_clone()?;
+        thread::spawn(move || {
+            thread::sleep(max);
+            let _ = hang_up.shutdown(Shutdown::Read);
+        });
+    }
+    Ok(rx)
+}
 
-        // Read user input
-        let mut line = String::new();
-        if reader.read_line(&mut line).unwrap_or(0) == 0 { break; }
-        let line = line.trim();
-        if line.is_empty() { continue; }
+// Replays run against a throwaway store so they never see or touch real accounts
+fn replay(path: &str, opts: &Options, catalog: &Catalog) -> io::Result<()> {
+    let transcript = fs::read_to_string(path)?;
+    let store_path = env::temp_dir().join(format!("corshop-replay-{}.json", process::id()));
+    let _ = fs::remove_file(&store_path);
+    let _ = fs::remove_file(store::ledger_path(&store_path));
+    rng::seed(opts.seed);
+    let store = Store::open(&store_path)?.with_password_rounds(1_000);
+    let result = replay::replay(&transcript, opts.protocol, &store, catalog, opts.clock);
+    let _ = fs::remove_file(&store_path);
+    let _ = fs::remove_file(store::ledger_path(&store_path));
+    match result {
+        Ok(out) => { print!("{}", out); Ok(()) }
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            process::exit(2);
+        }
+    }
+}
 
-        // Parse out the command
-        let mut parts = line.split_whitespace();
-        let cmd = parts.next().unwrap_or("");
+fn main() -> io::Result<()> {
+    let opts = parse_args(env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}\n{}", e, USAGE);
+        process::exit(2);
+    });
+    let catalog = Arc::new(Catalog::load(env::var("CORSHOP_CATALOG").unwrap_or_else(|_| "catalog.toml".to_string()))?);
+    if let Some(path) = &opts.replay { return replay(path, &opts, &catalog); }
+    let store = Arc::new(Store::open(env::var("CORSHOP_STORE").unwrap_or_else(|_| "corshop.json".to_string()))?);
 
-        match cmd {
-            "list" => {
-                // List table of our items
-                let _ = writeln!(writer, "{:<3} | {:>7} | {}", "ID", "PRICE", "NAME");
-                let _ = writeln!(writer, "----+-------+------------------------------");
-                for it in items() {
-                    let _ = writeln!(writer, "{:<3} | {:>7} | {}", it.id, it.price, it.name);
-                }
+    // Reload the catalog on SIGHUP, keeping the old one if the new file is broken
+    let mut signals = Signals::new([SIGHUP])?;
+    let (reloading, restocking) = (catalog.clone(), store.clone());
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            let before = reloading.snapshot();
+            match reloading.reload() {
+                Ok(n) => eprintln!("catalog: reloaded {} items", n),
+                Err(e) => { eprintln!("catalog: {}, keeping the current one", e); continue; }
             }
-            "balance" => {
-                // Show the current balance in corns
-                let _ = writeln!(writer, "Balance: {} corns", balance);
+            match restocking.update(|s| Ok::<_, io::Error>(s.reconcile_stock(&before, &reloading.snapshot(), unix_now()))) {
+                Ok(0) => {}
+                Ok(n) => eprintln!("catalog: changed the stock of {} items", n),
+                Err(e) => eprintln!("catalog: could not change stock: {}", e),
             }
-            "buy" => {
-                // Attempt to parse the id and quantity, else fall back to id 0 and qty 1.
-                let id: u32 = parts.next().and_then(|i| i.parse().ok()).unwrap_or(0);
-                let qty: u32 = parts.next().and_then(|q| q.parse().ok()).unwrap_or(1);
-
-                if qty == 0 {
-                    // ???
-                    let _ = writeln!(
-                        writer,
-                        "Thats not how buying stuff works.",
-                    );
-                    continue;
-                }
-
-                if let Some(item) = items().into_iter().find(|it| it.id == id) {
-                    // Calculate the total cost of this purchase
-                    let total: u64 = (item.price as u32 * qty) as u64;
+        }
+    });
 
-                    if balance >= total {
-                        // User can purchase, handle the purchase
-                        balance = balance - total;
-                        let _ = writeln!(
-                            writer,
-                            "Purchased {} x {} for {} corns.",
-                            qty, item.name, total
-                        );
-                        // We need to take quantities into account sometime but its not like people got any corn.
-                        if item.id == 1 {
-                            let _ = writeln!(
-                                writer,
-                                "(╥﹏╥)",
-                            );
-                        } else if item.id == 2 {
-                            let _ = writeln!(
-                                writer,
-                                "-ˋˏ✄┈┈┈┈",
-                            );
-                        } else if item.id == 3 {
-                            let _ = writeln!(
-                                writer,
-                                "{}",
-                                heap
-                            );
-                        } else if item.id == 4 {
-                            let _ = writeln!(
-                                writer,
-                                "{}",
-                                SOURCE
-                            );
-                        }
-                    } else {
-                        // User should seriously invest in some corns
-                        let _ = writeln!(
-                            writer,
-                            "Insufficient balance. Need {}, have {}.",
-                            total, balance
-                        );
-                    }
-                } else {
-                    // I mean this is what we get for only having 3 products...
-                    let _ = writeln!(writer, "Unknown item id. Try `list`.");
-                }
-            }
-            "help" => { let _ = writeln!(writer, "{}", banner()); }
-            "quit" | "exit" => { let _ = writeln!(writer, "bye!"); break; }
-            _ => { let _ = writeln!(writer, "Unknown command. Try `help`."); }
+    // Settle auctions as their deadlines pass, even if nobody is looking at them
+    let (settling, settling_catalog) = (store.clone(), catalog.clone());
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(1));
+        match auction::settle_due(&settling, &settling_catalog.snapshot(), unix_now()) {
+            Ok(0) => {}
+            Ok(n) => eprintln!("auction: settled {} auctions", n),
+            Err(e) => eprintln!("auction: {}", e),
         }
+    });
+
+    let limits = opts.limits();
+    if let Some(addr) = opts.listen {
+        let config = ServerConfig { addr, protocol: opts.protocol, max_sessions: opts.max_sessions, limits };
+        let threads = server::blocking_threads(config.max_sessions);
+        let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().max_blocking_threads(threads).build()?;
+        return runtime.block_on(server::serve(config, store, catalog));
     }
-}
 
-fn main() -> io::Result<()> {
-    let heap = env::var("HEAP").unwrap_or_else(|_| "Got some random garbage, are you running this on your own machine or something?".to_string());
-    let stdin = io::stdin();
+    #[cfg(feature = "tui")]
+    if opts.tui { return cor_shop::tui::shop(&store, &catalog); }
+
     let stdout = io::stdout();
-    let reader = BufReader::new(stdin.lock());
+    let reader = BufReader::new(timed_stdin(&limits)?);
     let writer = stdout.lock();
-    shop(reader, writer, &heap);
+    opts.protocol.run(reader, writer, &store, &catalog, limits);
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,104 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// An amount of corns. All arithmetic is checked, so a price or balance can never wrap around.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Corns(u64);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MoneyError { Overflow, Underflow }
+
+impl fmt::Display for MoneyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MoneyError::Overflow => write!(f, "that is more corns than exist"),
+            MoneyError::Underflow => write!(f, "that would leave a negative amount of corns"),
+        }
+    }
+}
+
+impl std::error::Error for MoneyError {}
+
+impl fmt::Display for Corns {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { self.0.fmt(f) }
+}
+
+impl Corns {
+    pub const ZERO: Corns = Corns(0);
+
+    pub const fn new(amount: u64) -> Corns { Corns(amount) }
+
+    pub const fn get(self) -> u64 { self.0 }
+
+    pub fn plus(self, other: Corns) -> Result<Corns, MoneyError> { self.0.checked_add(other.0).map(Corns).ok_or(MoneyError::Overflow) }
+
+    pub fn minus(self, other: Corns) -> Result<Corns, MoneyError> { self.0.checked_sub(other.0).map(Corns).ok_or(MoneyError::Underflow) }
+
+    /// `pct` percent of this amount, rounded down. Never more than the amount itself for `pct <= 100`.
+    pub fn percent(self, pct: u8) -> Corns { Corns((self.0 as u128 * pct.min(100) as u128 / 100) as u64) }
+
+    /// `n / d` of this amount, rounded down. Never more than the amount itself for `n <= d`.
+    pub fn share(self, n: u64, d: u64) -> Corns { if d == 0 { Corns::ZERO } else { Corns((self.0 as u128 * n.min(d) as u128 / d as u128) as u64) } }
+
+    /// The cost of `qty` units at this unit price.
+    pub fn times(self, qty: u64) -> Result<Corns, MoneyError> { self.0.checked_mul(qty).map(Corns).ok_or(MoneyError::Overflow) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn total_is_never_below_price_times_qty(price: u64, qty: u64) {
+            let exact = price as u128 * qty as u128;
+            match Corns::new(price).times(qty) {
+                Ok(total) => prop_assert_eq!(total.get() as u128, exact),
+                Err(e) => {
+                    prop_assert_eq!(e, MoneyError::Overflow);
+                    prop_assert!(exact > u64::MAX as u128);
+                }
+            }
+        }
+
+        #[test]
+        fn paying_never_wraps(balance: u64, total: u64) {
+            match Corns::new(balance).minus(Corns::new(total)) {
+                Ok(left) => prop_assert_eq!(left.get() as u128 + total as u128, balance as u128),
+                Err(e) => {
+                    prop_assert_eq!(e, MoneyError::Underflow);
+                    prop_assert!(total > balance);
+                }
+            }
+        }
+
+        #[test]
+        fn discount_never_exceeds_amount(amount: u64, pct: u8) {
+            let off = Corns::new(amount).percent(pct);
+            prop_assert!(off.get() <= amount);
+            prop_assert!(Corns::new(amount).minus(off).is_ok());
+        }
+
+        #[test]
+        fn selling_in_parts_never_pays_more_than_the_whole(amount: u64, d in 1..u64::MAX, n: u64) {
+            let n = n % (d + 1);
+            let whole = Corns::new(amount);
+            let (a, b) = (whole.share(n, d), whole.share(d - n, d));
+            prop_assert!(a.plus(b).is_ok_and(|sum| sum <= whole));
+        }
+
+        #[test]
+        fn credit_never_wraps(a: u64, b: u64) {
+            match Corns::new(a).plus(Corns::new(b)) {
+                Ok(sum) => prop_assert_eq!(sum.get() as u128, a as u128 + b as u128),
+                Err(e) => {
+                    prop_assert_eq!(e, MoneyError::Overflow);
+                    prop_assert!(a as u128 + b as u128 > u64::MAX as u128);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::catalog::{one, Item};
+use crate::money::{Corns, MoneyError};
+
+// Checkout gives up looking for a cheaper mix of bundles after this many, so a cart with absurd
+// quantities can't stall the shop. Real carts come nowhere near it.
+const MAX_COMBINATIONS: usize = 100_000;
+
+/// A volume price: buying at least `min` units of an item costs `price` per unit.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Tier {
+    pub min: u64,
+    pub price: Corns,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct BundleItem {
+    pub item: u32,
+    #[serde(default = "one")]
+    pub qty: u64,
+}
+
+/// A `[[bundle]]` entry in the catalog: everything in `items` together for `price`.
+#[derive(Clone, Deserialize)]
+pub struct Bundle {
+    pub id: u32,
+    pub name: String,
+    pub items: Vec<BundleItem>,
+    pub price: Corns,
+}
+
+impl Item {
+    /// The unit price when buying `qty` units: the best tier `qty` reaches, or the list price.
+    pub fn unit_price(&self, qty: u64) -> Corns {
+        self.tiers.iter().filter(|t| qty >= t.min).map(|t| t.price).fold(self.price, Corns::min)
+    }
+
+    pub fn cost(&self, qty: u64) -> Result<Corns, MoneyError> { self.unit_price(qty).times(qty) }
+}
+
+/// How a set of lines is charged. `costs` follows the order of the lines, and each bundle's price
+/// is spread over the lines it covers so refunds and coupons still work per item.
+pub struct Quote {
+    pub costs: Vec<Corns>,
+    pub bundles: Vec<(Bundle, u64)>,
+    pub total: Corns,
+}
+
+/// Prices `lines`, choosing the cheapest mix of `bundles` and volume tiers that covers them exactly.
+pub fn quote(lines: &[(Item, u64)], bundles: &[Bundle]) -> Result<Quote, MoneyError> {
+    let index: HashMap<u32, usize> = lines.iter().enumerate().map(|(i, (it, _))| (it.id, i)).collect();
+    let usable: Vec<(&Bundle, Vec<(usize, u64)>)> = bundles.iter().filter_map(|b| {
+        let needs = b.items.iter().map(|bi| index.get(&bi.item).map(|&i| (i, bi.qty))).collect::<Option<Vec<_>>>()?;
+        Some((b, needs))
+    }).collect();
+
+    let mut search = Search { lines, usable: &usable, counts: vec![0; usable.len()], best: None, tried: 0 };
+    let mut left: Vec<u64> = lines.iter().map(|&(_, qty)| qty).collect();
+    search.run(0, &mut left, Corns::ZERO);
+    let (total, counts) = match search.best {
+        Some(best) => best,
+        None => return Err(MoneyError::Overflow),
+    };
+
+    // Whatever the bundles don't cover is charged at its tier price
+    let mut left: Vec<u64> = lines.iter().map(|&(_, qty)| qty).collect();
+    let mut costs = vec![Corns::ZERO; lines.len()];
+    let mut used = Vec::new();
+    for ((bundle, needs), &n) in usable.iter().zip(&counts) {
+        if n == 0 { continue; }
+        let price = bundle.price.times(n)?;
+        let worth = needs.iter().try_fold(Corns::ZERO, |sum, &(i, qty)| sum.plus(lines[i].0.price.times(qty * n)?))?;
+        let mut spread = Corns::ZERO;
+        for (k, &(i, qty)) in needs.iter().enumerate() {
+            left[i] -= qty * n;
+            // The last line takes the rounding so the shares add up to the bundle price
+            let share = if k + 1 == needs.len() { price.minus(spread)? } else { price.share(lines[i].0.price.times(qty * n)?.get(), worth.get()) };
+            spread = spread.plus(share)?;
+            costs[i] = costs[i].plus(share)?;
+        }
+        used.push(((*bundle).clone(), n));
+    }
+    for (i, (item, _)) in lines.iter().enumerate() {
+        costs[i] = costs[i].plus(item.cost(left[i])?)?;
+    }
+    Ok(Quote { costs, bundles: used, total })
+}
+
+struct Search<'a> {
+    lines: &'a [(Item, u64)],
+    usable: &'a [(&'a Bundle, Vec<(usize, u64)>)],
+    counts: Vec<u64>,
+    best: Option<(Corns, Vec<u64>)>,
+    tried: usize,
+}
+
+impl Search<'_> {
+    // Tries every number of bundle `k` that fits in `left`, most first
+    fn run(&mut self, k: usize, left: &mut [u64], spent: Corns) {
+        if self.tried >= MAX_COMBINATIONS { return; }
+        if k == self.usable.len() {
+            self.tried += 1;
+            let rest = self.lines.iter().zip(left.iter()).try_fold(spent, |sum, ((it, _), &qty)| sum.plus(it.cost(qty)?));
+            if let Ok(total) = rest {
+                if self.best.as_ref().is_none_or(|(best, _)| total < *best) { self.best = Some((total, self.counts.clone())); }
+            }
+            return;
+        }
+        let (bundle, needs) = &self.usable[k];
+        // No more than the cart holds, and no more than a total that still fits in a balance
+        let affordable = (u64::MAX - spent.get()).checked_div(bundle.price.get()).unwrap_or(u64::MAX);
+        let fits = needs.iter().map(|&(i, qty)| left[i] / qty).min().unwrap_or(0).min(affordable);
+        for n in (0..=fits).rev() {
+            // Stop here too, or a huge quantity keeps this loop counting down long after the search is over
+            if self.tried >= MAX_COMBINATIONS { break; }
+            let Ok(spent) = bundle.price.times(n).and_then(|p| spent.plus(p)) else { continue };
+            for &(i, qty) in needs { left[i] -= qty * n; }
+            self.counts[k] = n;
+            self.run(k + 1, left, spent);
+            for &(i, qty) in needs { left[i] += qty * n; }
+        }
+        self.counts[k] = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::catalog::Snapshot;
+
+    const CATALOG: &str = r#"
+        [[item]]
+        id = 1
+        name = "tears"
+        price = 2000
+        tiers = [{ min = 10, price = 1900 }]
+        delivery = { kind = "text", text = "tears" }
+
+        [[item]]
+        id = 2
+        name = "heap"
+        price = 1000
+        delivery = { kind = "text", text = "heap" }
+
+        [[bundle]]
+        id = 1
+        name = "Tears and heap"
+        items = [{ item = 1 }, { item = 2 }]
+        price = 2500
+
+        [[bundle]]
+        id = 2
+        name = "Lots of heap"
+        items = [{ item = 2, qty = 3 }]
+        price = 2000
+    "#;
+
+    fn lines(qty: &[u64]) -> (Vec<(Item, u64)>, Vec<Bundle>) {
+        let snapshot: Snapshot = toml::from_str(CATALOG).unwrap();
+        (snapshot.items.into_iter().zip(qty.iter().copied()).collect(), snapshot.bundles)
+    }
+
+    fn used(quote: &Quote) -> Vec<(u32, u64)> { quote.bundles.iter().map(|(b, n)| (b.id, *n)).collect() }
+
+    #[test]
+    fn picks_the_cheapest_mix() {
+        // 1 tears + 4 heap: one of each bundle is 4500, the pair bundle and 3 loose heap is 5500
+        let (lines, bundles) = lines(&[1, 4]);
+        let quote = quote(&lines, &bundles).unwrap();
+        assert_eq!(quote.total, Corns::new(4500));
+        assert_eq!(used(&quote), vec![(1, 1), (2, 1)]);
+    }
+
+    #[test]
+    fn charges_leftovers_at_their_tier_price() {
+        // The pair bundle takes one of each, leaving 11 tears at the 10+ price
+        let (lines, bundles) = lines(&[12, 1]);
+        let quote = quote(&lines, &bundles).unwrap();
+        assert_eq!(used(&quote), vec![(1, 1)]);
+        assert_eq!(quote.total, Corns::new(2500 + 11 * 1900));
+    }
+
+    #[test]
+    fn bundle_shares_add_up_to_the_bundle_price() {
+        let (lines, bundles) = lines(&[1, 1]);
+        let quote = quote(&lines, &bundles).unwrap();
+        assert_eq!(quote.costs.iter().map(|c| c.get()).sum::<u64>(), 2500);
+        assert_eq!(quote.total, Corns::new(2500));
+        // Split in proportion to list prices, 2000:1000
+        assert_eq!(quote.costs, vec![Corns::new(1666), Corns::new(834)]);
+    }
+
+    #[test]
+    fn huge_quantities_come_back_promptly() {
+        let (lines, bundles) = lines(&[u64::MAX, u64::MAX]);
+        let start = Instant::now();
+        let _ = quote(&lines, &bundles);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
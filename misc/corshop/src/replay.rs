@@ -0,0 +1,112 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+use std::rc::Rc;
+
+use crate::catalog::Catalog;
+use crate::clock::{self, unix_now};
+use crate::limits::Limits;
+use crate::store::Store;
+use crate::Protocol;
+
+/// One line of a transcript. Lines are fed to the shop as typed, except for:
+///
+/// - `# ...` comments, which are skipped
+/// - `@clock <unix time>` and `@clock +<secs>`, which set or advance the clock before the next line is read
+/// - `@session`, which hangs up and starts a new session, so several users can take turns
+/// - `@ <text>`, which feeds `<text>` for the rare input that starts with `#` or `@`
+enum Step { Input(String), Clock(u64), Advance(u64), Session }
+
+pub struct ReplayError { pub line: usize, pub message: String }
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "line {}: {}", self.line, self.message) }
+}
+
+fn parse(src: &str) -> Result<Vec<Step>, ReplayError> {
+    let mut steps = Vec::new();
+    for (i, line) in src.lines().enumerate() {
+        let bad = |message: &str| ReplayError { line: i + 1, message: message.to_string() };
+        let step = match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+            _ if line.starts_with('#') => continue,
+            _ if line.starts_with("@ ") => Step::Input(line[2..].to_string()),
+            _ if !line.starts_with('@') => Step::Input(line.to_string()),
+            ["@session"] => Step::Session,
+            ["@clock", t] => match t.strip_prefix('+') {
+                Some(secs) => secs.parse().map(Step::Advance).map_err(|_| bad("@clock +<secs> needs a number of seconds"))?,
+                None => t.parse().map(Step::Clock).map_err(|_| bad("@clock needs a unix time or +<secs>"))?,
+            },
+            _ => return Err(bad("unknown directive, expected @clock, @session or `@ <text>`")),
+        };
+        steps.push(step);
+    }
+    Ok(steps)
+}
+
+// Output shared between the shop and the feed, so input shows up where it was typed
+#[derive(Clone, Default)]
+struct Screen(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Screen {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> { self.0.borrow_mut().write(buf) }
+
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+/// Hands the shop one transcript line at a time. A line is only fetched once the shop has
+/// answered the one before, so clock changes land between the right two commands.
+struct Feed { steps: VecDeque<Step>, line: Vec<u8>, pos: usize, screen: Screen, echo: &'static str }
+
+impl Read for Feed {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.fill_buf()?.read(buf)?;
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for Feed {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.pos == self.line.len() {
+            match self.steps.pop_front() {
+                Some(Step::Input(line)) => {
+                    let _ = writeln!(self.screen, "{}{}", self.echo, line);
+                    (self.line, self.pos) = (format!("{}\n", line).into_bytes(), 0);
+                }
+                Some(Step::Clock(t)) => clock::set_now(t),
+                Some(Step::Advance(secs)) => clock::set_now(unix_now().saturating_add(secs)),
+                // Sessions are split up front, see `replay`
+                Some(Step::Session) | None => break,
+            }
+        }
+        Ok(&self.line[self.pos..])
+    }
+
+    fn consume(&mut self, n: usize) { self.pos = (self.pos + n).min(self.line.len()); }
+}
+
+/// Runs `transcript` against the shop and returns everything it printed, with the input echoed in
+/// place. The clock starts at `start` and only moves when the transcript says so, so the output
+/// is the same on every run as long as the random generator is seeded too.
+pub fn replay(transcript: &str, protocol: Protocol, store: &Store, catalog: &Catalog, start: u64) -> Result<String, ReplayError> {
+    let steps = parse(transcript)?;
+    clock::set_now(start);
+    let screen = Screen::default();
+    // JSON clients get no prompt, so mark their lines to tell them apart from replies
+    let echo = match protocol { Protocol::Text => "", Protocol::Json => "> " };
+    let mut sessions = vec![VecDeque::new()];
+    for step in steps {
+        match step {
+            Step::Session => sessions.push(VecDeque::new()),
+            step => sessions.last_mut().unwrap().push_back(step),
+        }
+    }
+    for (i, steps) in sessions.into_iter().enumerate() {
+        if i > 0 { let _ = writeln!(screen.clone(), "@session"); }
+        let feed = Feed { steps, line: Vec::new(), pos: 0, screen: screen.clone(), echo };
+        protocol.run(feed, screen.clone(), store, catalog, Limits::default());
+    }
+    let out = screen.0.borrow();
+    Ok(String::from_utf8_lossy(&out).into_owned())
+}
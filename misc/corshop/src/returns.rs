@@ -0,0 +1,139 @@
+use std::fmt;
+use std::io;
+
+use serde::Deserialize;
+
+use crate::ledger::{user_book, Kind, Leg, SHOP};
+use crate::money::{Corns, MoneyError};
+use crate::store::{Account, State, Store};
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct ReturnsConfig {
+    /// Seconds after a purchase during which it can be refunded in full. 0 turns refunds off.
+    pub refund_window: u64,
+    /// Percentage of what was paid that the shop gives back when an item is sold back.
+    pub sell_back_percent: u8,
+}
+
+impl Default for ReturnsConfig {
+    fn default() -> ReturnsConfig { ReturnsConfig { refund_window: 3600, sell_back_percent: 50 } }
+}
+
+pub enum ReturnError {
+    UnknownOrder(u64),
+    AlreadyReturned(u64),
+    WindowClosed(u64),
+    FinalSaleOrder(u64),
+    FinalSale(String),
+    NotOwned { name: String, have: u64 },
+    Money(MoneyError),
+    Io(io::Error),
+}
+
+impl From<io::Error> for ReturnError { fn from(e: io::Error) -> ReturnError { ReturnError::Io(e) } }
+
+impl From<MoneyError> for ReturnError { fn from(e: MoneyError) -> ReturnError { ReturnError::Money(e) } }
+
+impl fmt::Display for ReturnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReturnError::UnknownOrder(id) => write!(f, "You have no order #{}.", id),
+            ReturnError::AlreadyReturned(id) => write!(f, "Part of order #{} was already refunded or sold back.", id),
+            ReturnError::WindowClosed(id) => write!(f, "Order #{} can no longer be refunded. Try `sell` instead.", id),
+            ReturnError::FinalSaleOrder(id) => write!(f, "Order #{} includes final sale items and can't be refunded.", id),
+            ReturnError::FinalSale(name) => write!(f, "{} is a final sale and can't be sold back.", name),
+            ReturnError::NotOwned { name, have } => write!(f, "You only have {} x {} to give back.", have, name),
+            ReturnError::Money(e) => write!(f, "Can't do that: {}.", e),
+            ReturnError::Io(_) => write!(f, "Could not save your return, try again later."),
+        }
+    }
+}
+
+/// What went back to the shop, and what the user got for it.
+pub struct Returned { pub items: Vec<(u32, u64)>, pub amount: Corns, pub balance: Corns }
+
+fn account<'s>(s: &'s mut State, user: &str) -> Result<&'s mut Account, ReturnError> {
+    s.accounts.get_mut(user).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such account").into())
+}
+
+fn give_back(acct: &mut Account, id: u32, qty: u64, name: &str) -> Result<(), ReturnError> {
+    let have = acct.owned.get(&id).copied().unwrap_or(0);
+    if have < qty { return Err(ReturnError::NotOwned { name: name.to_string(), have }); }
+    if have == qty { acct.owned.remove(&id); } else { acct.owned.insert(id, have - qty); }
+    Ok(())
+}
+
+/// Undoes order `order` in full: every item goes back on the shelf and everything paid goes back
+/// to the user. Coupons used on the order stay used. Orders with a final sale line in them can't
+/// be refunded at all.
+pub fn refund(store: &Store, user: &str, order: u64, config: &ReturnsConfig, now: u64, name: impl Fn(u32) -> String) -> Result<Returned, ReturnError> {
+    store.update(|s| {
+        let acct = account(s, user)?;
+        let lines: Vec<usize> = (0..acct.purchases.len()).filter(|&i| order != 0 && acct.purchases[i].order == order).collect();
+        let Some(&first) = lines.first() else { return Err(ReturnError::UnknownOrder(order)) };
+        if lines.iter().any(|&i| acct.purchases[i].returned > 0) { return Err(ReturnError::AlreadyReturned(order)); }
+        if config.refund_window == 0 || now > acct.purchases[first].time.saturating_add(config.refund_window) {
+            return Err(ReturnError::WindowClosed(order));
+        }
+        if lines.iter().any(|&i| acct.purchases[i].final_sale) { return Err(ReturnError::FinalSaleOrder(order)); }
+
+        let mut returned = Returned { items: Vec::new(), amount: Corns::ZERO, balance: Corns::ZERO };
+        for &i in &lines {
+            let p = &mut acct.purchases[i];
+            p.returned = p.qty;
+            let (id, qty, total) = (p.item, p.qty, p.total);
+            give_back(acct, id, qty, &name(id))?;
+            returned.amount = returned.amount.plus(total)?;
+            returned.items.push((id, qty));
+        }
+        acct.balance = acct.balance.plus(returned.amount)?;
+        returned.balance = acct.balance;
+        for &(id, qty) in &returned.items { s.return_stock(id, qty)?; }
+
+        let book = user_book(user);
+        let mut legs = vec![Leg::Corns { from: SHOP.to_string(), to: book.clone(), amount: returned.amount }];
+        legs.extend(returned.items.iter().map(|&(id, qty)| Leg::Item { id, qty, from: book.clone(), to: SHOP.to_string() }));
+        s.record(Kind::Refund, now, format!("refund of order #{}", order), legs);
+        Ok(returned)
+    })
+}
+
+/// Sells `qty` units of item `id` back to the shop for `sell_back_percent` of what was paid for
+/// them. The oldest purchases are sold first, and only bought units can be sold. Final sales never
+/// count as bought here.
+pub fn sell(store: &Store, user: &str, id: u32, qty: u64, name: &str, config: &ReturnsConfig, now: u64) -> Result<Returned, ReturnError> {
+    store.update(|s| {
+        let acct = account(s, user)?;
+        let bought: u64 = acct.purchases.iter().filter(|p| p.item == id && !p.final_sale).map(|p| p.qty - p.returned).sum();
+        let have = bought.min(acct.owned.get(&id).copied().unwrap_or(0));
+        if have < qty {
+            if have == 0 && acct.purchases.iter().any(|p| p.item == id && p.final_sale) { return Err(ReturnError::FinalSale(name.to_string())); }
+            return Err(ReturnError::NotOwned { name: name.to_string(), have });
+        }
+
+        let mut paid = Corns::ZERO;
+        let mut left = qty;
+        for p in acct.purchases.iter_mut().filter(|p| p.item == id && !p.final_sale) {
+            if left == 0 { break; }
+            let take = left.min(p.qty - p.returned);
+            // Each unit is worth its share of what the line cost, coupon discount included
+            paid = paid.plus(p.total.share(take, p.qty))?;
+            p.returned += take;
+            left -= take;
+        }
+        give_back(acct, id, qty, name)?;
+        let amount = paid.percent(config.sell_back_percent);
+        acct.balance = acct.balance.plus(amount)?;
+        let balance = acct.balance;
+        s.return_stock(id, qty)?;
+
+        let book = user_book(user);
+        let legs = vec![
+            Leg::Corns { from: SHOP.to_string(), to: book.clone(), amount },
+            Leg::Item { id, qty, from: book, to: SHOP.to_string() },
+        ];
+        s.record(Kind::Sell, now, format!("sold back {} x {}", qty, name), legs);
+        Ok(Returned { items: vec![(id, qty)], amount, balance })
+    })
+}
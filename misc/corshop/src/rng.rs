@@ -0,0 +1,18 @@
+use std::sync::Mutex;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+// Set by `replay` so challenges and salts repeat on every run
+static SEEDED: Mutex<Option<StdRng>> = Mutex::new(None);
+
+/// Makes every later `bytes` call come from a generator seeded with `seed`. Only for replays.
+pub fn seed(seed: u64) { *SEEDED.lock().unwrap() = Some(StdRng::seed_from_u64(seed)); }
+
+/// `N` random bytes, from the seeded generator if there is one.
+pub fn bytes<const N: usize>() -> [u8; N] {
+    match &mut *SEEDED.lock().unwrap() {
+        Some(rng) => rng.random(),
+        None => rand::random(),
+    }
+}
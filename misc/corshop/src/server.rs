@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinSet;
+
+use crate::catalog::Catalog;
+use crate::limits::Limits;
+use crate::Protocol;
+use crate::store::Store;
+
+pub struct ServerConfig { pub addr: String, pub protocol: Protocol, pub max_sessions: usize, pub limits: Limits }
+
+// How long a session that was hung up on gets to tell its client why before the connection is cut
+const NOTICE_GRACE: Duration = Duration::from_secs(5);
+
+/// Blocking threads the runtime needs so every allowed session gets one, with a few to spare.
+pub fn blocking_threads(max_sessions: usize) -> usize { max_sessions + 4 }
+
+struct Server {
+    config: ServerConfig,
+    store: Arc<Store>,
+    catalog: Arc<Catalog>,
+    // Connections with a running session, so shutdown can hang up on them
+    open: Arc<Mutex<HashMap<u64, TcpStream>>>,
+}
+
+/// Accepts connections until SIGINT or SIGTERM, running one shop session per connection on the
+/// blocking pool. On shutdown every session finishes its current command and is then disconnected,
+/// or cut off if it is still stuck writing a few seconds later.
+///
+/// Sessions share their line reader, limits and protocols with stdin and replay, which are all
+/// blocking, so each one keeps a thread for as long as it is connected. `max_sessions` bounds how
+/// many there are, and the runtime needs a blocking pool at least that big (see `blocking_threads`).
+pub async fn serve(config: ServerConfig, store: Arc<Store>, catalog: Arc<Catalog>) -> io::Result<()> {
+    let listener = TcpListener::bind(&config.addr).await?;
+    eprintln!("server: listening on {}", listener.local_addr()?);
+    let slots = Arc::new(Semaphore::new(config.max_sessions));
+    let server = Server { config, store, catalog, open: Arc::default() };
+    let mut sessions = JoinSet::new();
+    let mut next_id = 0u64;
+    let mut term = signal(SignalKind::terminate())?;
+
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(conn) => conn,
+                Err(e) => { eprintln!("server: accept failed: {}", e); continue; }
+            },
+            _ = tokio::signal::ctrl_c() => break,
+            _ = term.recv() => break,
+            // Reap finished sessions as we go
+            Some(_) = sessions.join_next(), if !sessions.is_empty() => continue,
+        };
+        // The session itself runs on a thread of its own, so it gets a blocking std stream
+        let stream = match stream.into_std().and_then(|s| s.set_nonblocking(false).map(|_| s)) {
+            Ok(s) => s,
+            Err(e) => { eprintln!("server: {}: {}", peer, e); continue; }
+        };
+        let Ok(permit) = slots.clone().try_acquire_owned() else {
+            let _ = (&stream).write_all(b"cor.shop is full, try again later.\n");
+            continue;
+        };
+        let id = next_id;
+        next_id += 1;
+        if let Err(e) = server.start(&mut sessions, id, peer, stream, permit) {
+            eprintln!("session {}: {}", id, e);
+        }
+    }
+
+    server.hang_up(Shutdown::Read);
+    // Sessions stuck writing to a client that stopped reading never notice, so cut them off
+    let finished = tokio::time::timeout(NOTICE_GRACE, async { while sessions.join_next().await.is_some() {} }).await;
+    if finished.is_err() {
+        server.hang_up(Shutdown::Both);
+        while sessions.join_next().await.is_some() {}
+    }
+    Ok(())
+}
+
+impl Server {
+    fn hang_up(&self, how: Shutdown) {
+        let open = self.open.lock().unwrap();
+        eprintln!("server: shutting down {} sessions", open.len());
+        for stream in open.values() { let _ = stream.shutdown(how); }
+    }
+
+    fn start(&self, sessions: &mut JoinSet<()>, id: u64, peer: SocketAddr, stream: TcpStream, permit: OwnedSemaphorePermit) -> io::Result<()> {
+        // A read that times out ends the session, after the client is told why. So does a write to a
+        // client that stopped reading, which would otherwise hold the thread forever
+        let limits = self.config.limits;
+        stream.set_read_timeout(limits.idle_timeout)?;
+        stream.set_write_timeout(limits.idle_timeout)?;
+        self.open.lock().unwrap().insert(id, stream.try_clone()?);
+        // Hang up on the session when its time is up, unless it ends first and cancels the timer. If
+        // it is still around after the grace period it is stuck writing, so cut it off altogether
+        let timer = limits.max_session.map(|max| {
+            let open = self.open.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(max).await;
+                if let Some(stream) = open.lock().unwrap().get(&id) { let _ = stream.shutdown(Shutdown::Read); }
+                tokio::time::sleep(NOTICE_GRACE).await;
+                if let Some(stream) = open.lock().unwrap().get(&id) { let _ = stream.shutdown(Shutdown::Both); }
+            }).abort_handle()
+        });
+        let (store, catalog, open, protocol) = (self.store.clone(), self.catalog.clone(), self.open.clone(), self.config.protocol);
+        eprintln!("session {}: {} connected", id, peer);
+        sessions.spawn_blocking(move || {
+            protocol.run(BufReader::new(&stream), BufWriter::new(&stream), &store, &catalog, limits);
+            // The goodbye has been written or has timed out, so nothing more goes either way
+            let _ = stream.shutdown(Shutdown::Both);
+            open.lock().unwrap().remove(&id);
+            if let Some(timer) = timer { timer.abort(); }
+            drop(permit);
+            eprintln!("session {}: closed", id);
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,588 @@
+use std::io;
+
+use serde::Serialize;
+
+use crate::admin;
+use crate::auction::{self, BidError, Status};
+use crate::cart::Cart;
+use crate::catalog::{Catalog, CatalogError, Item};
+use crate::checkout::{purchase, BuyError, Receipt};
+use crate::clock::unix_now;
+use crate::coupon::CouponError;
+use crate::faucet::Challenge;
+use crate::fulfillment::Order;
+use crate::grammar::{self, Shape};
+use crate::ledger::{user_book, Kind, Leg, ADMIN, FAUCET};
+use crate::money::Corns;
+use crate::pricing::{self, Bundle, Tier};
+use crate::returns::{self, ReturnError};
+use crate::store::{LoginError, Notice, Store};
+use crate::transfer::{self, TransferError};
+
+/// Everything a client can ask the shop to do. Both front-ends build these with `grammar`.
+pub enum Command {
+    Login { user: String, password: String },
+    Register { user: String, password: String },
+    List,
+    Info { id: u32 },
+    Buy { id: u32, qty: u64, coupon: Option<String> },
+    CartAdd { id: u32, qty: u64 },
+    CartRemove { id: u32, qty: Option<u64> },
+    CartShow,
+    Redeem { code: String },
+    Checkout,
+    Refund { order: u64 },
+    Auctions,
+    Bid { auction: u32, amount: Corns },
+    Give { user: String, amount: Corns },
+    Gift { user: String, id: u32, qty: u64 },
+    Sell { id: u32, qty: u64 },
+    Balance,
+    Mine { answer: Option<String> },
+    Inventory,
+    Ledger,
+    Admin(AdminCommand),
+    Help { command: Option<String> },
+    Quit,
+}
+
+/// Shop operations behind the admin token.
+pub enum AdminCommand {
+    Unlock { token: String },
+    Restock { id: u32, qty: u64 },
+    Price { id: u32, price: Corns },
+    Add { id: u32, name: String, price: Corns, stock: Option<u64>, text: Option<String> },
+    Remove { id: u32 },
+    Grant { user: String, amount: Corns },
+    Sessions,
+}
+
+#[derive(Serialize)]
+pub struct Listing { pub id: u32, pub name: String, pub price: Corns, pub tiers: Vec<Tier>, pub stock: Option<u64>, pub final_sale: bool, pub description: String }
+
+#[derive(Serialize)]
+pub struct BundlePart { pub id: u32, pub qty: u64, pub name: String }
+
+/// A bundle as `list` shows it. `worth` is what its items cost on their own.
+#[derive(Serialize)]
+pub struct BundleListing { pub id: u32, pub name: String, pub price: Corns, pub worth: Option<Corns>, pub items: Vec<BundlePart> }
+
+/// A bundle a cart or order was priced with, `qty` times at `price` each.
+#[derive(Serialize)]
+pub struct BundleUse { pub id: u32, pub name: String, pub qty: u64, pub price: Corns }
+
+#[derive(Serialize)]
+pub struct Owned { pub id: u32, pub qty: u64, pub name: Option<String> }
+
+#[derive(Serialize)]
+pub struct CartLine { pub id: u32, pub qty: u64, pub name: Option<String>, pub price: Option<Corns>, pub cost: Option<Corns> }
+
+#[derive(Serialize)]
+pub struct BoughtLine { pub id: u32, pub name: String, pub qty: u64, pub price: Corns, pub cost: Corns, pub discount: Corns }
+
+/// One ledger transaction as seen from a user's book.
+#[derive(Serialize)]
+pub struct LedgerEntry { pub id: u64, pub time: u64, pub kind: Kind, pub memo: String, pub received: Corns, pub paid: Corns, pub balance: Corns }
+
+/// An auction as seen by one user. `high_bid` is `None` until someone bids, and after the auction
+/// ends it is the winning bid.
+#[derive(Serialize)]
+pub struct AuctionListing {
+    pub id: u32,
+    pub item: u32,
+    pub name: String,
+    pub qty: u64,
+    pub status: Status,
+    pub starts_at: Option<u64>,
+    pub ends_at: u64,
+    pub high_bid: Option<Corns>,
+    pub bids: u64,
+    // Only while the auction is open
+    pub min_bid: Option<Corns>,
+    pub yours: bool,
+}
+
+/// One argument of a command, as explained by `help <command>`.
+#[derive(Serialize)]
+pub struct ParamHelp { pub name: &'static str, pub required: bool, pub about: &'static str }
+
+#[derive(Serialize)]
+pub struct Online { pub user: String, pub sessions: usize, pub balance: Corns }
+
+/// What a fulfillment produced. `content` is `None` if delivery failed.
+#[derive(Serialize)]
+pub struct Delivery { pub id: u32, pub name: String, pub content: Option<String> }
+
+#[derive(Serialize)]
+pub struct Bought { pub order: u64, pub lines: Vec<BoughtLine>, pub bundles: Vec<BundleUse>, pub coupon: Option<String>, pub discount: Corns, pub total: Corns, pub deliveries: Vec<Delivery> }
+
+#[derive(Serialize)]
+#[serde(tag = "reply", rename_all = "snake_case")]
+pub enum Reply {
+    LoggedIn { user: String, created: bool, notices: Vec<Notice> },
+    Items { items: Vec<Listing>, bundles: Vec<BundleListing> },
+    Info { item: Listing },
+    Balance { balance: Corns },
+    Inventory { items: Vec<Owned> },
+    Ledger { entries: Vec<LedgerEntry> },
+    Bought(Bought),
+    CheckedOut(Bought),
+    Refunded { order: u64, amount: Corns, balance: Corns },
+    Sold { id: u32, name: String, qty: u64, amount: Corns, balance: Corns },
+    Gave { user: String, amount: Corns, balance: Corns },
+    Auctions { auctions: Vec<AuctionListing> },
+    BidPlaced { auction: u32, amount: Corns, balance: Corns },
+    Gifted { user: String, id: u32, name: String, qty: u64 },
+    CartUpdated { id: u32, name: Option<String>, qty: u64 },
+    Cart { lines: Vec<CartLine>, bundles: Vec<BundleUse>, total: Option<Corns> },
+    CouponApplied { code: String, discount: String },
+    Challenge { prefix: String, difficulty: u32, reward: Corns },
+    Mined { reward: Corns, balance: Corns },
+    AdminUnlocked,
+    Restocked { id: u32, name: String, stock: u64 },
+    PriceChanged { id: u32, name: String, old: Corns, new: Corns },
+    ItemAdded { id: u32, name: String },
+    ItemRemoved { id: u32, name: String },
+    Granted { user: String, amount: Corns, balance: Corns },
+    Sessions { sessions: Vec<Online> },
+    Help,
+    Usage { command: &'static str, usage: String, summary: &'static str, params: Vec<ParamHelp> },
+    Bye,
+}
+
+/// A command that could not be carried out. `code` is stable, `message` is for humans. `cause` is
+/// the storage error behind a "storage" failure, logged by `execute` and never shown to the client.
+#[derive(Serialize)]
+pub struct Failure { pub code: &'static str, pub message: String, #[serde(skip)] pub cause: Option<String> }
+
+impl Failure {
+    pub fn new(code: &'static str, message: impl Into<String>) -> Failure { Failure { code, message: message.into(), cause: None } }
+
+    pub fn storage(e: &io::Error, message: impl Into<String>) -> Failure { Failure { cause: Some(e.to_string()), ..Failure::new("storage", message) } }
+}
+
+impl From<BuyError> for Failure {
+    fn from(e: BuyError) -> Failure {
+        let code = match &e {
+            BuyError::Insufficient { .. } => "insufficient_balance",
+            BuyError::OutOfStock { .. } => "out_of_stock",
+            BuyError::UnknownItem(_) => "unknown_item",
+            BuyError::Coupon(_) => "coupon",
+            BuyError::Money(_) => "money",
+            BuyError::Io(io) => return Failure::storage(io, e.to_string()),
+        };
+        Failure::new(code, e.to_string())
+    }
+}
+
+impl From<ReturnError> for Failure {
+    fn from(e: ReturnError) -> Failure {
+        let code = match &e {
+            ReturnError::UnknownOrder(_) => "unknown_order",
+            ReturnError::AlreadyReturned(_) => "already_returned",
+            ReturnError::WindowClosed(_) => "refund_window_closed",
+            ReturnError::FinalSaleOrder(_) | ReturnError::FinalSale(_) => "final_sale",
+            ReturnError::NotOwned { .. } => "not_owned",
+            ReturnError::Money(_) => "money",
+            ReturnError::Io(io) => return Failure::storage(io, e.to_string()),
+        };
+        Failure::new(code, e.to_string())
+    }
+}
+
+impl From<BidError> for Failure {
+    fn from(e: BidError) -> Failure {
+        let code = match &e {
+            BidError::UnknownAuction(_) => "unknown_auction",
+            BidError::NotOpen(_) => "auction_closed",
+            BidError::TooLow { .. } => "bid_too_low",
+            BidError::Insufficient { .. } => "insufficient_balance",
+            BidError::Money(_) => "money",
+            BidError::Io(io) => return Failure::storage(io, e.to_string()),
+        };
+        Failure::new(code, e.to_string())
+    }
+}
+
+impl From<TransferError> for Failure {
+    fn from(e: TransferError) -> Failure {
+        let code = match &e {
+            TransferError::ToSelf => "to_self",
+            TransferError::UnknownUser(_) => "unknown_user",
+            TransferError::Insufficient { .. } => "insufficient_balance",
+            TransferError::NotOwned { .. } => "not_owned",
+            TransferError::CornLimit { .. } | TransferError::ItemLimit { .. } => "daily_limit",
+            TransferError::RecipientCornLimit { .. } | TransferError::RecipientItemLimit { .. } => "daily_limit",
+            TransferError::Money(_) => "money",
+            TransferError::Io(io) => return Failure::storage(io, e.to_string()),
+        };
+        Failure::new(code, e.to_string())
+    }
+}
+
+impl From<CatalogError> for Failure {
+    fn from(e: CatalogError) -> Failure { Failure::new("catalog", format!("Catalog not changed: {}.", e)) }
+}
+
+fn bundle_uses(bundles: &[(Bundle, u64)]) -> Vec<BundleUse> {
+    bundles.iter().map(|(b, qty)| BundleUse { id: b.id, name: b.name.clone(), qty: *qty, price: b.price }).collect()
+}
+
+fn unknown_item() -> Failure { Failure::new("unknown_item", "Unknown item id. Try `list`.") }
+
+fn bad_quantity() -> Failure { Failure::new("bad_quantity", "Thats not how buying stuff works.") }
+
+fn challenge_expired() -> Failure { Failure::new("challenge_expired", "That challenge has expired. Send `mine` for a new one.") }
+
+fn coupon_failure(e: CouponError) -> Failure { Failure::new("coupon", format!("Can't use that coupon: {}.", e)) }
+
+/// One client's conversation with the shop. Front-ends turn input into `Command`s and render the
+/// `Reply` or `Failure` that comes back; all the shop logic lives here.
+pub struct Session<'a> {
+    store: &'a Store,
+    catalog: &'a Catalog,
+    user: Option<String>,
+    cart: Cart,
+    challenge: Option<Challenge>,
+    coupon: Option<String>,
+    // Unlocked with the admin token for the rest of the session
+    admin: bool,
+}
+
+impl<'a> Session<'a> {
+    pub fn new(store: &'a Store, catalog: &'a Catalog) -> Session<'a> {
+        Session { store, catalog, user: None, cart: Cart::default(), challenge: None, coupon: None, admin: false }
+    }
+
+    pub fn execute(&mut self, cmd: Command) -> Result<Reply, Failure> {
+        let result = self.dispatch(cmd);
+        if let Err(Failure { cause: Some(e), .. }) = &result { eprintln!("store: {}", e); }
+        result
+    }
+
+    fn dispatch(&mut self, cmd: Command) -> Result<Reply, Failure> {
+        let user = match (&cmd, &self.user) {
+            (Command::Login { .. } | Command::Register { .. } | Command::Help { .. } | Command::Quit, _) => String::new(),
+            (_, Some(user)) => user.clone(),
+            (_, None) => return Err(Failure::new("not_logged_in", "Log in first.")),
+        };
+        match cmd {
+            Command::Login { user, password } => self.login(user, &password, false),
+            Command::Register { user, password } => self.login(user, &password, true),
+            Command::List => {
+                let snapshot = self.catalog.snapshot();
+                let items = snapshot.items.iter().map(|it| self.listing(it)).collect();
+                let bundles = snapshot.bundles.iter().map(|b| {
+                    let parts: Vec<(Item, u64)> = b.items.iter().filter_map(|bi| self.catalog.get(bi.item).map(|it| (it, bi.qty))).collect();
+                    let worth = parts.iter().try_fold(Corns::ZERO, |sum, (it, qty)| sum.plus(it.price.times(*qty)?)).ok();
+                    let items = parts.into_iter().map(|(it, qty)| BundlePart { id: it.id, qty, name: it.name }).collect();
+                    BundleListing { id: b.id, name: b.name.clone(), price: b.price, worth, items }
+                }).collect();
+                Ok(Reply::Items { items, bundles })
+            }
+            Command::Info { id } => self.catalog.get(id).map(|it| Reply::Info { item: self.listing(&it) }).ok_or_else(unknown_item),
+            Command::Balance => Ok(Reply::Balance { balance: self.store.account(&user).map_or(Corns::ZERO, |a| a.balance) }),
+            Command::Inventory => {
+                // Everything this user has bought and still owns
+                let owned = self.store.account(&user).map(|a| a.owned).unwrap_or_default();
+                let items = owned.into_iter().map(|(id, qty)| Owned { id, qty, name: self.catalog.get(id).map(|it| it.name) }).collect();
+                Ok(Reply::Inventory { items })
+            }
+            Command::Ledger => {
+                // Every transaction that touched this user's corns, with the running balance
+                let book = user_book(&user);
+                let mut balance = Corns::ZERO;
+                let entries = self.store.read(|s| s.ledger.iter().filter_map(|tx| {
+                    let (received, paid) = tx.corns_for(&book);
+                    let touched = tx.legs.iter().any(|l| matches!(l, Leg::Corns { from, to, .. } if *from == book || *to == book));
+                    if !touched { return None; }
+                    let (received, paid) = (Corns::new(received as u64), Corns::new(paid as u64));
+                    balance = balance.plus(received).and_then(|b| b.minus(paid)).unwrap_or(balance);
+                    Some(LedgerEntry { id: tx.id, time: tx.time, kind: tx.kind, memo: tx.memo.clone(), received, paid, balance })
+                }).collect());
+                Ok(Reply::Ledger { entries })
+            }
+            Command::Buy { id, qty, coupon } => {
+                if qty == 0 { return Err(bad_quantity()); }
+                let item = self.catalog.get(id).ok_or_else(unknown_item)?;
+                self.not_on_auction(&item)?;
+                let snapshot = self.catalog.snapshot();
+                let c = coupon.as_deref().map(|c| snapshot.coupon(c).ok_or(CouponError::Unknown)).transpose().map_err(coupon_failure)?;
+                // User can purchase if they have the corns and we have the stock, handle the purchase
+                let receipt = purchase(self.store, &user, &[(item, qty)], &snapshot.bundles, c, unix_now())?;
+                Ok(Reply::Bought(self.bought(&user, receipt, coupon)))
+            }
+            Command::CartAdd { id, qty } => {
+                let item = self.catalog.get(id).ok_or_else(unknown_item)?;
+                if qty == 0 { return Err(bad_quantity()); }
+                let qty = self.cart.add(id, qty)
+                    .ok_or_else(|| Failure::new("bad_quantity", format!("That's more {} than anyone could carry.", item.name)))?;
+                Ok(Reply::CartUpdated { id, name: Some(item.name), qty })
+            }
+            Command::CartRemove { id, qty } => {
+                let left = self.cart.remove(id, qty).ok_or_else(|| Failure::new("not_in_cart", format!("Item {} is not in your cart.", id)))?;
+                Ok(Reply::CartUpdated { id, name: None, qty: left })
+            }
+            Command::CartShow => {
+                // Priced the way checkout would, bundles and tiers included
+                let cart: Vec<(u32, u64, Option<Item>)> = self.cart.lines().map(|(id, qty)| (id, qty, self.catalog.get(id))).collect();
+                let known: Vec<(Item, u64)> = cart.iter().filter_map(|(_, qty, it)| it.clone().map(|it| (it, *qty))).collect();
+                let quote = pricing::quote(&known, &self.catalog.snapshot().bundles).ok();
+                let mut costs = quote.as_ref().map(|q| q.costs.iter().copied());
+                let total = quote.as_ref().filter(|_| known.len() == cart.len()).map(|q| q.total);
+                let bundles = quote.as_ref().map_or_else(Vec::new, |q| bundle_uses(&q.bundles));
+                let lines = cart.into_iter().map(|(id, qty, item)| {
+                    let cost = item.as_ref().and_then(|_| costs.as_mut().and_then(Iterator::next));
+                    CartLine { id, qty, price: item.as_ref().map(|it| it.unit_price(qty)), name: item.map(|it| it.name), cost }
+                }).collect();
+                Ok(Reply::Cart { lines, bundles, total })
+            }
+            Command::Redeem { code } => {
+                let snapshot = self.catalog.snapshot();
+                let c = snapshot.coupon(&code).ok_or(CouponError::Unknown)
+                    .and_then(|c| self.store.read(|s| c.check(s, &user, unix_now())).map(|_| c))
+                    .map_err(coupon_failure)?;
+                self.coupon = Some(c.code.clone());
+                Ok(Reply::CouponApplied { code: c.code.clone(), discount: c.discount.to_string() })
+            }
+            Command::Checkout => {
+                if self.cart.is_empty() { return Err(Failure::new("cart_empty", "Your cart is empty.")); }
+                let snapshot = self.catalog.snapshot();
+                let lines = self.cart.lines()
+                    .map(|(id, qty)| self.catalog.get(id).map(|it| (it, qty)).ok_or(BuyError::UnknownItem(id)))
+                    .collect::<Result<Vec<(Item, u64)>, BuyError>>()?;
+                for (item, _) in &lines { self.not_on_auction(item)?; }
+                let c = self.coupon.as_deref().map(|c| snapshot.coupon(c).ok_or(CouponError::Unknown)).transpose().map_err(coupon_failure)?;
+                let receipt = purchase(self.store, &user, &lines, &snapshot.bundles, c, unix_now())?;
+                self.cart.clear();
+                let coupon = self.coupon.take();
+                Ok(Reply::CheckedOut(self.bought(&user, receipt, coupon)))
+            }
+            Command::Refund { order } => {
+                let snapshot = self.catalog.snapshot();
+                let name = |id| self.catalog.get(id).map_or_else(|| format!("item {}", id), |it| it.name);
+                let r = returns::refund(self.store, &user, order, &snapshot.returns, unix_now(), name)?;
+                Ok(Reply::Refunded { order, amount: r.amount, balance: r.balance })
+            }
+            Command::Sell { id, qty } => {
+                if qty == 0 { return Err(bad_quantity()); }
+                let name = self.catalog.get(id).map_or_else(|| format!("item {}", id), |it| it.name);
+                let r = returns::sell(self.store, &user, id, qty, &name, &self.catalog.snapshot().returns, unix_now())?;
+                Ok(Reply::Sold { id, name, qty, amount: r.amount, balance: r.balance })
+            }
+            Command::Auctions => {
+                let (snapshot, now) = (self.catalog.snapshot(), unix_now());
+                self.settle(now);
+                let auctions = self.store.read(|s| snapshot.auctions.iter().map(|a| {
+                    let lot = s.auctions.get(&a.id);
+                    let high = lot.and_then(|l| l.bidder.as_ref().or(l.winner.as_ref()).map(|who| (who, l.bid)));
+                    AuctionListing {
+                        id: a.id,
+                        item: a.item,
+                        name: snapshot.items.iter().find(|it| it.id == a.item).map_or_else(|| format!("item {}", a.item), |it| it.name.clone()),
+                        qty: a.qty,
+                        status: a.status(now),
+                        starts_at: a.starts_at,
+                        ends_at: a.ends_at,
+                        high_bid: high.map(|(_, bid)| bid),
+                        bids: lot.map_or(0, |l| l.bids),
+                        min_bid: a.min_bid(lot).ok().filter(|_| matches!(a.status(now), Status::Open)),
+                        yours: high.is_some_and(|(who, _)| *who == user),
+                    }
+                }).collect());
+                Ok(Reply::Auctions { auctions })
+            }
+            Command::Bid { auction, amount } => {
+                let now = unix_now();
+                self.settle(now);
+                let balance = auction::bid(self.store, &self.catalog.snapshot(), &user, auction, amount, now)?;
+                Ok(Reply::BidPlaced { auction, amount, balance })
+            }
+            Command::Give { user: to, amount } => {
+                if amount == Corns::ZERO { return Err(Failure::new("bad_amount", "Give at least one corn.")); }
+                let balance = transfer::give(self.store, &user, &to, amount, &self.catalog.snapshot().transfers, unix_now())?;
+                Ok(Reply::Gave { user: to, amount, balance })
+            }
+            Command::Gift { user: to, id, qty } => {
+                if qty == 0 { return Err(bad_quantity()); }
+                let name = self.catalog.get(id).map_or_else(|| format!("item {}", id), |it| it.name);
+                transfer::gift(self.store, &user, &to, (id, qty), &name, &self.catalog.snapshot().transfers, unix_now())?;
+                Ok(Reply::Gifted { user: to, id, name, qty })
+            }
+            Command::Mine { answer } => self.mine(&user, answer),
+            Command::Admin(cmd) => self.admin(&user, cmd),
+            Command::Help { command: None } => Ok(Reply::Help),
+            Command::Help { command: Some(words) } => {
+                let spec = grammar::find(&words).ok_or_else(|| Failure::new("unknown_command", format!("There is no `{}` command. Try `help`.", words)))?;
+                let params = spec.params.iter().map(|p| ParamHelp { name: p.name, required: matches!(p.shape, Shape::Required), about: p.about }).collect();
+                Ok(Reply::Usage { command: spec.words, usage: spec.usage(), summary: spec.summary, params })
+            }
+            Command::Quit => Ok(Reply::Bye),
+        }
+    }
+
+    // Logs in as `user`, creating the account first if `register` is set
+    fn login(&mut self, user: String, password: &str, register: bool) -> Result<Reply, Failure> {
+        if self.user.is_some() { return Err(Failure::new("logged_in", "You are already logged in.")); }
+        let done = if register { self.store.register(&user, password) } else { self.store.login(&user, password) };
+        match done {
+            Ok(()) => {}
+            Err(LoginError::BadUsername) => return Err(Failure::new("bad_username", "Usernames are 1-32 characters of letters, digits, '_' or '-'.")),
+            Err(LoginError::BadPassword) => return Err(Failure::new("bad_password", "Wrong password.")),
+            Err(LoginError::UnknownUser) => return Err(Failure::new("unknown_user", format!("There is no account {}. Register to create it.", user))),
+            Err(LoginError::Taken) => return Err(Failure::new("username_taken", format!("The username {} is taken.", user))),
+            Err(LoginError::Io(e)) => return Err(Failure::storage(&e, "Could not log you in, try again later.")),
+        }
+        self.user = Some(user.clone());
+        let notices = self.store.take_notices(&user);
+        Ok(Reply::LoggedIn { user, created: register, notices })
+    }
+
+    fn mine(&mut self, user: &str, answer: Option<String>) -> Result<Reply, Failure> {
+        let faucet = self.catalog.snapshot().faucet.clone();
+        let required = faucet.difficulty(self.store.account(user).map_or(0, |a| a.mined));
+        let Some(answer) = answer else {
+            // Hand out a fresh challenge unless the current one is still good
+            if self.challenge.as_ref().is_none_or(|c| c.difficulty < required) { self.challenge = Some(Challenge::new(required)); }
+            let c = self.challenge.as_ref().unwrap();
+            return Ok(Reply::Challenge { prefix: c.prefix.clone(), difficulty: c.difficulty, reward: faucet.reward });
+        };
+        let c = self.challenge.as_ref().ok_or_else(|| Failure::new("no_challenge", "No challenge yet. Send `mine` to get one."))?;
+        let difficulty = c.difficulty;
+        if difficulty < required {
+            // Solved elsewhere in the meantime, so this one no longer pays out
+            self.challenge = None;
+            return Err(challenge_expired());
+        }
+        if !c.solved_by(&answer) {
+            return Err(Failure::new("wrong_answer", format!("Nope, that hash only starts with {} zero bits.", c.zero_bits(&answer))));
+        }
+        let paid = self.store.update(|s| {
+            let acct = s.accounts.get_mut(user).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such account"))?;
+            // Another session may have mined since the check above, raising the bar past this challenge
+            if faucet.difficulty(acct.mined) > difficulty { return Ok(None); }
+            acct.balance = acct.balance.plus(faucet.reward)?;
+            acct.mined = acct.mined.saturating_add(1);
+            let balance = acct.balance;
+            let leg = Leg::Corns { from: FAUCET.to_string(), to: user_book(user), amount: faucet.reward };
+            s.record(Kind::Faucet, unix_now(), format!("mined at difficulty {}", difficulty), vec![leg]);
+            Ok::<_, BuyError>(Some(balance))
+        })?;
+        self.challenge = None;
+        let balance = paid.ok_or_else(challenge_expired)?;
+        Ok(Reply::Mined { reward: faucet.reward, balance })
+    }
+
+    fn admin(&mut self, user: &str, cmd: AdminCommand) -> Result<Reply, Failure> {
+        let config = self.catalog.snapshot().admin.clone();
+        let reply = match cmd {
+            AdminCommand::Unlock { token } => {
+                match self.store.check_admin(unix_now(), || config.check(&token)) {
+                    Some(true) => {}
+                    Some(false) => {
+                        config.log(user, "failed to unlock the admin console");
+                        return Err(Failure::new("bad_token", "That is not the admin token."));
+                    }
+                    None => {
+                        config.log(user, "was refused the admin console after too many wrong tokens");
+                        return Err(Failure::new("locked_out", "Too many wrong admin tokens lately. The console is locked for now."));
+                    }
+                }
+                config.log(user, "unlocked the admin console");
+                self.admin = true;
+                Reply::AdminUnlocked
+            }
+            _ if !self.admin => return Err(Failure::new("not_admin", "Unlock the admin console first with `admin <token>`.")),
+            AdminCommand::Restock { id, qty } => {
+                let item = self.catalog.get(id).ok_or_else(unknown_item)?;
+                let stock = self.store.update(|s| Ok::<_, BuyError>(s.restock(&item, qty, unix_now())?))?
+                    .ok_or_else(|| Failure::new("unlimited", format!("{} has unlimited stock.", item.name)))?;
+                config.log(user, &format!("restocked {} x {} (item {}), now {} in stock", qty, item.name, id, stock));
+                Reply::Restocked { id, name: item.name, stock }
+            }
+            AdminCommand::Price { id, price } => {
+                let item = self.catalog.get(id).ok_or_else(unknown_item)?;
+                self.catalog.edit(|doc| admin::set_price(doc, id, price))?;
+                config.log(user, &format!("changed the price of {} (item {}) from {} to {}", item.name, id, item.price, price));
+                Reply::PriceChanged { id, name: item.name, old: item.price, new: price }
+            }
+            AdminCommand::Add { id, name, price, stock, text } => {
+                let before = self.catalog.snapshot();
+                self.catalog.edit(|doc| admin::add_item(doc, id, &name, price, stock, text.as_deref().unwrap_or(&name)))?;
+                // An id that was sold before may still have stock on the books
+                self.store.update(|s| Ok::<_, BuyError>(s.reconcile_stock(&before, &self.catalog.snapshot(), unix_now())))?;
+                config.log(user, &format!("added {} (item {}) at {}", name, id, price));
+                Reply::ItemAdded { id, name }
+            }
+            AdminCommand::Remove { id } => {
+                let item = self.catalog.get(id).ok_or_else(unknown_item)?;
+                self.catalog.edit(|doc| admin::remove_item(doc, id))?;
+                config.log(user, &format!("removed {} (item {})", item.name, id));
+                Reply::ItemRemoved { id, name: item.name }
+            }
+            AdminCommand::Grant { user: to, amount } => {
+                let balance = self.store.update(|s| {
+                    let Some(acct) = s.accounts.get_mut(&to) else { return Ok(None) };
+                    acct.balance = acct.balance.plus(amount)?;
+                    let balance = acct.balance;
+                    let leg = Leg::Corns { from: ADMIN.to_string(), to: user_book(&to), amount };
+                    s.record(Kind::Grant, unix_now(), format!("granted by {}", user), vec![leg]);
+                    Ok::<_, BuyError>(Some(balance))
+                })?.ok_or_else(|| Failure::new("unknown_user", format!("There is no user {}.", to)))?;
+                config.log(user, &format!("granted {} corns to {}, balance now {}", amount, to, balance));
+                Reply::Granted { user: to, amount, balance }
+            }
+            AdminCommand::Sessions => {
+                let online = self.store.online();
+                let sessions = self.store.read(|s| online.into_iter().map(|(user, sessions)| {
+                    let balance = s.accounts.get(&user).map_or(Corns::ZERO, |a| a.balance);
+                    Online { user, sessions, balance }
+                }).collect());
+                config.log(user, "listed sessions");
+                Reply::Sessions { sessions }
+            }
+        };
+        Ok(reply)
+    }
+
+    // Auction deadlines are also enforced by the background settler, this just makes them exact
+    fn settle(&self, now: u64) {
+        if let Err(e) = auction::settle_due(self.store, &self.catalog.snapshot(), now) { eprintln!("auction: {}", e); }
+    }
+
+    /// Items up for auction can't also be bought at their fixed price.
+    fn not_on_auction(&self, item: &Item) -> Result<(), Failure> {
+        let snapshot = self.catalog.snapshot();
+        let open = snapshot.auctions.iter().find(|a| a.item == item.id && !self.store.read(|s| s.auctions.get(&a.id).is_some_and(|l| l.settled)));
+        match open {
+            Some(a) => Err(Failure::new("on_auction", format!("{} is up for auction, see auction #{}.", item.name, a.id))),
+            None => Ok(()),
+        }
+    }
+
+    fn listing(&self, it: &Item) -> Listing {
+        let stock = self.store.read(|s| s.stock_left(it));
+        Listing { id: it.id, name: it.name.clone(), price: it.price, tiers: it.tiers.clone(), stock, final_sale: it.final_sale, description: it.description.clone() }
+    }
+
+    fn bought(&self, user: &str, receipt: Receipt, coupon: Option<String>) -> Bought {
+        let deliveries = receipt.lines.iter().map(|line| {
+            let mut out = Vec::new();
+            let content = match line.item.delivery.fulfill(&Order { user, item: line.item.id, qty: line.qty }, &mut out) {
+                Ok(()) => Some(String::from_utf8_lossy(&out).into_owned()),
+                Err(e) => { eprintln!("fulfillment: item {} for {}: {}", line.item.id, user, e); None }
+            };
+            Delivery { id: line.item.id, name: line.item.name.clone(), content }
+        }).collect();
+        let lines = receipt.lines.into_iter().map(|l| BoughtLine {
+            id: l.item.id, price: l.item.unit_price(l.qty), name: l.item.name, qty: l.qty, cost: l.cost, discount: l.discount,
+        }).collect();
+        Bought { order: receipt.order, lines, bundles: bundle_uses(&receipt.bundles), coupon, discount: receipt.discount, total: receipt.total, deliveries }
+    }
+}
+
+impl Drop for Session<'_> {
+    fn drop(&mut self) {
+        if let Some(user) = &self.user { self.store.logout(user); }
+    }
+}
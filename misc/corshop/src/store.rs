@@ -0,0 +1,580 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::admin;
+use crate::auction::Lot;
+use crate::catalog::{Item, Snapshot};
+use crate::clock::unix_now;
+use crate::ledger::{user_book, Kind, Leg, Ledger, Transaction, ESCROW, OPENING, SHOP, SUPPLIER};
+use crate::money::{Corns, MoneyError};
+use crate::rng;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Purchase {
+    // Ledger transaction that paid for this line, shared by every line bought together
+    #[serde(default)]
+    pub order: u64,
+    #[serde(default)]
+    pub time: u64,
+    pub item: u32,
+    pub qty: u64,
+    pub total: Corns,
+    // Units since refunded or sold back to the shop
+    #[serde(default)]
+    pub returned: u64,
+    // Bought as a final sale or won at auction, so it can't be refunded or sold back
+    #[serde(default)]
+    pub final_sale: bool,
+}
+
+/// Something that happened to an account while its owner was away.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Notice { pub time: u64, pub message: String }
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Account {
+    pub salt: String,
+    pub password_hash: String,
+    // PBKDF2 iterations behind `password_hash`. 0 for accounts from before it, which used a single
+    // salted SHA-256 and move over at their next login.
+    #[serde(default)]
+    pub rounds: u32,
+    pub balance: Corns,
+    // Faucet challenges solved so far, which sets the difficulty of the next one
+    #[serde(default)]
+    pub mined: u32,
+    #[serde(default)]
+    pub purchases: Vec<Purchase>,
+    // item id -> quantity owned
+    #[serde(default)]
+    pub owned: BTreeMap<u32, u64>,
+    // coupon code -> times this user redeemed it
+    #[serde(default)]
+    pub coupons: BTreeMap<String, u64>,
+    // Shown and cleared at the next login
+    #[serde(default)]
+    pub inbox: Vec<Notice>,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct State {
+    pub accounts: BTreeMap<String, Account>,
+    // item id -> units left, seeded from the catalog the first time an item sells
+    #[serde(default)]
+    pub stock: BTreeMap<u32, u64>,
+    // coupon code -> times it was redeemed by anyone
+    #[serde(default)]
+    pub coupon_uses: BTreeMap<String, u64>,
+    // auction id -> bidding so far, created by the first bid
+    #[serde(default)]
+    pub auctions: BTreeMap<u32, Lot>,
+    // Kept in a file of its own that only ever grows, see `Store`
+    #[serde(skip)]
+    pub ledger: Ledger,
+    // Transactions in the ledger file this state accounts for. Any after them were written by an
+    // update that never got to save its state.
+    #[serde(default)]
+    recorded: u64,
+}
+
+impl State {
+    /// Units of `item` left, or `None` if the item is unlimited.
+    pub fn stock_left(&self, item: &Item) -> Option<u64> {
+        item.stock.map(|initial| self.stock.get(&item.id).copied().unwrap_or(initial))
+    }
+
+    /// Takes `qty` units of `item` out of stock, returning what is left on failure.
+    pub fn take_stock(&mut self, item: &Item, qty: u64, now: u64) -> Result<(), u64> {
+        let Some(left) = self.stock_left(item) else { return Ok(()) };
+        if left < qty { return Err(left); }
+        self.open_stock(item, left, now);
+        self.stock.insert(item.id, left - qty);
+        Ok(())
+    }
+
+    /// Puts the catalog's opening stock on the books the first time an item's stock changes.
+    fn open_stock(&mut self, item: &Item, left: u64, now: u64) {
+        if self.stock.contains_key(&item.id) { return; }
+        let opening = Leg::Item { id: item.id, qty: left, from: SUPPLIER.to_string(), to: SHOP.to_string() };
+        self.record(Kind::Stock, now, format!("opening stock of {}", item.name), vec![opening]);
+        self.stock.insert(item.id, left);
+    }
+
+    /// Adds `qty` units from the supplier, returning the new stock level. `None` if the item is
+    /// unlimited and so can't be restocked.
+    pub fn restock(&mut self, item: &Item, qty: u64, now: u64) -> Result<Option<u64>, MoneyError> {
+        let Some(left) = self.stock_left(item) else { return Ok(None) };
+        let stock = left.checked_add(qty).ok_or(MoneyError::Overflow)?;
+        self.open_stock(item, left, now);
+        let leg = Leg::Item { id: item.id, qty, from: SUPPLIER.to_string(), to: SHOP.to_string() };
+        self.record(Kind::Stock, now, format!("restocked {} x {}", qty, item.name), vec![leg]);
+        self.stock.insert(item.id, stock);
+        Ok(Some(stock))
+    }
+
+    /// Carries changes to the catalog's `stock` over to items already on the books, which would
+    /// otherwise keep counting down from the number they first sold with. Raising it by n restocks
+    /// n units and lowering it writes n off, while an item new to the catalog starts over at its
+    /// `stock`. Returns how many items changed.
+    pub fn reconcile_stock(&mut self, before: &Snapshot, after: &Snapshot, now: u64) -> usize {
+        let mut changed = 0;
+        for item in &after.items {
+            let (Some(stock), Some(&left)) = (item.stock, self.stock.get(&item.id)) else { continue };
+            let target = match before.items.iter().find(|it| it.id == item.id).and_then(|it| it.stock) {
+                Some(was) => (left as i128 + stock as i128 - was as i128).clamp(0, u64::MAX as i128) as u64,
+                None => stock,
+            };
+            let (from, to, qty, how) = match target.cmp(&left) {
+                Ordering::Equal => continue,
+                Ordering::Greater => (SUPPLIER, SHOP, target - left, "raised"),
+                Ordering::Less => (SHOP, SUPPLIER, left - target, "lowered"),
+            };
+            let leg = Leg::Item { id: item.id, qty, from: from.to_string(), to: to.to_string() };
+            self.record(Kind::Stock, now, format!("catalog stock of {} {} by {}", item.name, how, qty), vec![leg]);
+            self.stock.insert(item.id, target);
+            changed += 1;
+        }
+        changed
+    }
+
+    /// Puts `qty` units of item `id` back on the shelf. Unlimited items have nothing to put back.
+    pub fn return_stock(&mut self, id: u32, qty: u64) -> Result<(), MoneyError> {
+        if let Some(left) = self.stock.get_mut(&id) { *left = left.checked_add(qty).ok_or(MoneyError::Overflow)?; }
+        Ok(())
+    }
+
+    /// Leaves `message` in `user`'s inbox for their next login.
+    pub fn notify(&mut self, user: &str, time: u64, message: String) {
+        if let Some(acct) = self.accounts.get_mut(user) { acct.inbox.push(Notice { time, message }); }
+    }
+
+    /// Appends a transaction to the ledger. Callers make the matching changes to balances and stock
+    /// in the same `Store::update`, so the two can never drift apart. Returns the transaction id.
+    pub fn record(&mut self, kind: Kind, time: u64, memo: String, legs: Vec<Leg>) -> u64 {
+        let id = self.ledger.len() as u64 + 1;
+        self.ledger.push(Transaction { id, time, kind, memo, legs });
+        id
+    }
+
+    /// Books everything a store from before the ledger holds as one opening transaction, so the
+    /// audit has something to replay. Does nothing if there is nothing to carry over.
+    fn open_books(&mut self, now: u64) {
+        let from = || OPENING.to_string();
+        let mut legs = Vec::new();
+        for (name, acct) in &self.accounts {
+            if acct.balance > Corns::ZERO { legs.push(Leg::Corns { from: from(), to: user_book(name), amount: acct.balance }); }
+            legs.extend(acct.owned.iter().filter(|(_, q)| **q > 0).map(|(&id, &qty)| Leg::Item { id, qty, from: from(), to: user_book(name) }));
+        }
+        legs.extend(self.stock.iter().filter(|(_, q)| **q > 0).map(|(&id, &qty)| Leg::Item { id, qty, from: from(), to: SHOP.to_string() }));
+        for lot in self.auctions.values().filter(|l| !l.settled && l.bidder.is_some()) {
+            legs.push(Leg::Corns { from: from(), to: ESCROW.to_string(), amount: lot.bid });
+        }
+        if legs.is_empty() { return; }
+        self.record(Kind::Opening, now, "carried over from before the ledger".to_string(), legs);
+    }
+
+    /// Gives a store with no ledger file the transactions it kept inline, or opening books if it
+    /// had none at all.
+    fn carry_over(&mut self, inline: Vec<Transaction>, now: u64) {
+        if !self.ledger.is_empty() { return; }
+        if inline.is_empty() { self.open_books(now) } else { inline.into_iter().for_each(|tx| self.ledger.push(tx)) }
+    }
+}
+
+pub enum LoginError { BadUsername, BadPassword, UnknownUser, Taken, Io(io::Error) }
+
+impl From<io::Error> for LoginError { fn from(e: io::Error) -> LoginError { LoginError::Io(e) } }
+
+/// Account storage shared by every session. Backed by a JSON file, which is rewritten on every
+/// change, and a ledger file next to it, which is only appended to.
+pub struct Store {
+    path: PathBuf,
+    ledger_path: PathBuf,
+    state: Mutex<State>,
+    // PBKDF2 iterations for new password hashes
+    password_rounds: u32,
+    // user -> sessions currently logged in as them, never saved
+    online: Mutex<BTreeMap<String, usize>>,
+    // When recent wrong admin tokens were tried, never saved
+    admin_failures: Mutex<Vec<u64>>,
+}
+
+fn hex(bytes: &[u8]) -> String { bytes.iter().map(|b| format!("{:02x}", b)).collect() }
+
+// Iterations for new password hashes, as recommended for PBKDF2-HMAC-SHA256
+pub const PASSWORD_ROUNDS: u32 = 600_000;
+
+fn hash_password(salt: &str, password: &str, rounds: u32) -> String {
+    if rounds == 0 {
+        let mut h = Sha256::new();
+        h.update(salt.as_bytes());
+        h.update(password.as_bytes());
+        return hex(&h.finalize());
+    }
+    let mut out = [0; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt.as_bytes(), rounds, &mut out);
+    hex(&out)
+}
+
+
+/// Where the ledger of the store at `path` is kept, one JSON transaction per line.
+pub fn ledger_path(path: &Path) -> PathBuf { path.with_extension("ledger") }
+
+fn invalid(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error { io::Error::new(io::ErrorKind::InvalidData, e) }
+
+// The saved state and the ledger an older store kept inline, or `None` if there is no store yet
+fn read_state(path: &Path) -> io::Result<Option<(State, Vec<Transaction>)>> {
+    let mut saved: serde_json::Value = match fs::read_to_string(path) {
+        Ok(s) => serde_json::from_str(&s).map_err(invalid)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let inline = match saved.as_object_mut().and_then(|o| o.remove("ledger")) {
+        Some(ledger) => serde_json::from_value(ledger).map_err(invalid)?,
+        None => Vec::new(),
+    };
+    Ok(Some((serde_json::from_value(saved).map_err(invalid)?, inline)))
+}
+
+// The first `recorded` transactions of a ledger file, the bytes they take up and the file's length
+fn read_ledger(path: &Path, recorded: u64) -> io::Result<(Vec<Transaction>, u64, u64)> {
+    let file = match File::open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == io::ErrorKind::NotFound && recorded == 0 => return Ok((Vec::new(), 0, 0)),
+        Err(e) => return Err(e),
+    };
+    let len = file.metadata()?.len();
+    let (mut ledger, mut kept) = (Vec::new(), 0);
+    for line in BufReader::new(file).lines() {
+        if ledger.len() as u64 == recorded { break; }
+        let line = line?;
+        ledger.push(serde_json::from_str(&line).map_err(invalid)?);
+        kept += line.len() as u64 + 1;
+    }
+    if (ledger.len() as u64) < recorded { return Err(invalid(format!("{} is missing transactions", path.display()))); }
+    Ok((ledger, kept, len))
+}
+
+fn valid_username(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 32 && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+impl Store {
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Store> {
+        let path = path.into();
+        let store = Store { ledger_path: ledger_path(&path), path, state: Mutex::default(), password_rounds: PASSWORD_ROUNDS, online: Mutex::default(), admin_failures: Mutex::default() };
+        let saved = read_state(&store.path)?;
+        let found = saved.is_some();
+        let (mut state, inline) = saved.unwrap_or_default();
+        let (ledger, kept, len) = read_ledger(&store.ledger_path, state.recorded)?;
+        if len > kept {
+            // Without the state there is no telling how much of the ledger was saved, so keep all of it
+            if !found {
+                return Err(invalid(format!("{} has transactions but {} is missing", store.ledger_path.display(), store.path.display())));
+            }
+            // Drop whatever an update wrote before it failed to save the state
+            OpenOptions::new().write(true).open(&store.ledger_path)?.set_len(kept)?;
+        }
+        state.ledger = Ledger::from(ledger);
+        let mut next = state.clone();
+        next.carry_over(inline, unix_now());
+        if !next.ledger.pending().is_empty() { store.commit(&mut state, next)?; }
+        *store.state.lock().unwrap() = state;
+        Ok(store)
+    }
+
+    /// Reads the store at `path` without ever writing to it, so it is safe next to a running shop.
+    /// Transactions appended past the saved state are left alone, and the books of an older store
+    /// are only opened in memory.
+    pub fn inspect(path: impl AsRef<Path>) -> io::Result<State> {
+        let path = path.as_ref();
+        let (mut state, inline) = read_state(path)?.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no store here"))?;
+        state.ledger = Ledger::from(read_ledger(&ledger_path(path), state.recorded)?.0);
+        state.carry_over(inline, unix_now());
+        Ok(state)
+    }
+
+    /// Hashes new passwords with `rounds` iterations instead of `PASSWORD_ROUNDS`. Only for stores
+    /// that are thrown away, like a replay's, where hashing would take up most of the run.
+    pub fn with_password_rounds(mut self, rounds: u32) -> Store {
+        self.password_rounds = rounds;
+        self
+    }
+
+    // A fresh salt and the hash of `password` with it, at the current work factor
+    fn new_password(&self, password: &str) -> (String, String) {
+        let salt = hex(&rng::bytes::<16>());
+        let hash = hash_password(&salt, password, self.password_rounds);
+        (salt, hash)
+    }
+
+    fn save(&self, state: &State) -> io::Result<()> {
+        // Write to a temporary file first so a crash never leaves a half-written store behind
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(state)?)?;
+        fs::rename(&tmp, &self.path)
+    }
+
+    /// Appends what `next` added to the ledger file, then saves `next` and makes it the current
+    /// state. The ledger file is cut back if saving fails, so the two never disagree.
+    fn commit(&self, state: &mut State, mut next: State) -> io::Result<()> {
+        let mut appended = None;
+        if !next.ledger.pending().is_empty() {
+            let mut lines = Vec::new();
+            for tx in next.ledger.pending() {
+                serde_json::to_writer(&mut lines, tx)?;
+                lines.push(b'\n');
+            }
+            let mut file = OpenOptions::new().create(true).append(true).open(&self.ledger_path)?;
+            let start = file.metadata()?.len();
+            if let Err(e) = file.write_all(&lines).and_then(|_| file.sync_data()) {
+                let _ = file.set_len(start);
+                return Err(e);
+            }
+            appended = Some((file, start));
+        }
+        next.recorded = next.ledger.len() as u64;
+        if let Err(e) = self.save(&next) {
+            if let Some((file, start)) = appended { let _ = file.set_len(start); }
+            return Err(e);
+        }
+        // Dropping the old state first leaves the history unshared, so it grows without a copy
+        *state = State::default();
+        next.ledger.commit();
+        *state = next;
+        Ok(())
+    }
+
+    /// Logs in as an existing `user`. Hashing is slow on purpose, so it happens without holding
+    /// the store up for other sessions.
+    pub fn login(&self, user: &str, password: &str) -> Result<(), LoginError> {
+        if !valid_username(user) { return Err(LoginError::BadUsername); }
+        let acct = self.account(user).ok_or(LoginError::UnknownUser)?;
+        if hash_password(&acct.salt, password, acct.rounds) != acct.password_hash { return Err(LoginError::BadPassword); }
+        // Older accounts move to the current work factor now that their password is known
+        if acct.rounds < self.password_rounds {
+            let (salt, hash) = self.new_password(password);
+            self.update(|s| {
+                if let Some(a) = s.accounts.get_mut(user) { (a.salt, a.password_hash, a.rounds) = (salt, hash, self.password_rounds); }
+                Ok::<_, LoginError>(())
+            })?;
+        }
+        *self.online.lock().unwrap().entry(user.to_string()).or_insert(0) += 1;
+        Ok(())
+    }
+
+    /// Creates the account `user` and logs in as it.
+    pub fn register(&self, user: &str, password: &str) -> Result<(), LoginError> {
+        if !valid_username(user) { return Err(LoginError::BadUsername); }
+        if self.read(|s| s.accounts.contains_key(user)) { return Err(LoginError::Taken); }
+        let (salt, password_hash) = self.new_password(password);
+        self.update(|s| {
+            // Someone else may have taken the name while the password was hashed
+            if s.accounts.contains_key(user) { return Err(LoginError::Taken); }
+            s.accounts.insert(user.to_string(), Account { salt, password_hash, rounds: self.password_rounds, ..Account::default() });
+            Ok(())
+        })?;
+        *self.online.lock().unwrap().entry(user.to_string()).or_insert(0) += 1;
+        Ok(())
+    }
+
+    /// Ends one session of `user` that logged in through `login`.
+    pub fn logout(&self, user: &str) {
+        let mut online = self.online.lock().unwrap();
+        if let Some(n) = online.get_mut(user) {
+            *n -= 1;
+            if *n == 0 { online.remove(user); }
+        }
+    }
+
+    /// Checks an admin token with `check` and counts it if it was wrong. Returns `None` without
+    /// checking while too many wrong tokens were tried lately, whichever sessions they came from.
+    pub fn check_admin(&self, now: u64, check: impl FnOnce() -> bool) -> Option<bool> {
+        let mut failures = self.admin_failures.lock().unwrap();
+        failures.retain(|&t| t + admin::ATTEMPT_WINDOW > now);
+        if failures.len() >= admin::MAX_ATTEMPTS { return None; }
+        let ok = check();
+        if !ok { failures.push(now); }
+        Some(ok)
+    }
+
+    /// Users with at least one session open, and how many.
+    pub fn online(&self) -> Vec<(String, usize)> { self.online.lock().unwrap().iter().map(|(u, n)| (u.clone(), *n)).collect() }
+
+    /// Empties `user`'s inbox. If that can't be saved the notices are kept for next time.
+    pub fn take_notices(&self, user: &str) -> Vec<Notice> {
+        let taken = self.update(|s| Ok::<_, io::Error>(s.accounts.get_mut(user).map(|a| std::mem::take(&mut a.inbox)).unwrap_or_default()));
+        taken.unwrap_or_else(|e| {
+            eprintln!("store: {}", e);
+            self.account(user).map(|a| a.inbox).unwrap_or_default()
+        })
+    }
+
+    pub fn read<T>(&self, f: impl FnOnce(&State) -> T) -> T { f(&self.state.lock().unwrap()) }
+
+    pub fn account(&self, user: &str) -> Option<Account> { self.read(|s| s.accounts.get(user).cloned()) }
+
+    /// Runs `f` as one transaction: its changes are persisted if it returns `Ok`, and thrown away
+    /// if it returns `Err` or saving fails.
+    pub fn update<T, E: From<io::Error>>(&self, f: impl FnOnce(&mut State) -> Result<T, E>) -> Result<T, E> {
+        let mut state = self.state.lock().unwrap();
+        let mut next = state.clone();
+        let out = f(&mut next)?;
+        self.commit(&mut state, next)?;
+        Ok(out)
+    }
+}
+
+/// An empty store in a file of its own, for tests.
+#[cfg(test)]
+pub fn scratch(name: &str) -> Store {
+    let path = std::env::temp_dir().join(format!("corshop-test-{}-{}.json", std::process::id(), name));
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_file(ledger_path(&path));
+    Store::open(path).unwrap().with_password_rounds(1_000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::{audit, FAUCET};
+
+    fn mine(store: &Store, user: &str, amount: u64) {
+        store.update(|s| {
+            let acct = s.accounts.get_mut(user).unwrap();
+            acct.balance = acct.balance.plus(Corns::new(amount)).unwrap();
+            let leg = Leg::Corns { from: FAUCET.to_string(), to: user_book(user), amount: Corns::new(amount) };
+            s.record(Kind::Faucet, 0, "mined".to_string(), vec![leg]);
+            Ok::<_, io::Error>(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn a_store_from_before_the_ledger_gets_opening_balances() {
+        let store = scratch("pre-ledger");
+        let old = r#"{"accounts": {"bob": {"salt": "", "password_hash": "", "balance": 1500, "owned": {"1": 2}}}, "stock": {"1": 8}}"#;
+        fs::write(&store.path, old).unwrap();
+        let store = Store::open(&store.path).unwrap();
+        store.read(|s| {
+            assert_eq!(s.ledger.len(), 1);
+            assert!(audit(s).is_empty(), "{:?}", audit(s));
+        });
+        // Opened again, the books are already open
+        let store = Store::open(&store.path).unwrap();
+        assert_eq!(store.read(|s| s.ledger.len()), 1);
+    }
+
+    #[test]
+    fn the_ledger_is_kept_apart_and_survives_a_reopen() {
+        let store = scratch("ledger-file");
+        store.register("bob", "hunter2").ok().unwrap();
+        mine(&store, "bob", 100);
+        mine(&store, "bob", 200);
+        assert!(!fs::read_to_string(&store.path).unwrap().contains("legs"));
+        assert_eq!(fs::read_to_string(&store.ledger_path).unwrap().lines().count(), 2);
+        let store = Store::open(&store.path).unwrap();
+        store.read(|s| {
+            assert_eq!(s.ledger.iter().map(|tx| tx.id).collect::<Vec<_>>(), [1, 2]);
+            assert!(audit(s).is_empty(), "{:?}", audit(s));
+        });
+    }
+
+    #[test]
+    fn transactions_whose_state_was_never_saved_are_dropped() {
+        let store = scratch("ledger-crash");
+        store.register("bob", "hunter2").ok().unwrap();
+        mine(&store, "bob", 100);
+        let kept = fs::read(&store.ledger_path).unwrap();
+        // As if the process died between appending to the ledger and saving the state
+        let mut file = OpenOptions::new().append(true).open(&store.ledger_path).unwrap();
+        file.write_all(b"{\"id\": 2, \"time\": 0, \"kind\": \"faucet\", \"memo\": \"lost\", \"legs\": []}\n{\"id\": 3, \"ti").unwrap();
+        let store = Store::open(&store.path).unwrap();
+        assert_eq!(store.read(|s| s.ledger.len()), 1);
+        assert_eq!(fs::read(&store.ledger_path).unwrap(), kept);
+        mine(&store, "bob", 200);
+        assert!(store.read(audit).is_empty());
+    }
+
+    #[test]
+    fn inspecting_a_store_never_writes_to_it() {
+        let store = scratch("inspect");
+        store.register("bob", "hunter2").ok().unwrap();
+        mine(&store, "bob", 100);
+        // A transaction a live shop has appended but not yet saved the state for
+        let mut file = OpenOptions::new().append(true).open(&store.ledger_path).unwrap();
+        file.write_all(b"{\"id\": 2, \"time\": 0, \"kind\": \"faucet\", \"memo\": \"pending\", \"legs\": []}\n").unwrap();
+        let ledger = fs::read(&store.ledger_path).unwrap();
+        let state = Store::inspect(&store.path).unwrap();
+        assert_eq!(state.ledger.len(), 1);
+        assert!(audit(&state).is_empty());
+        assert_eq!(fs::read(&store.ledger_path).unwrap(), ledger);
+
+        let old = scratch("inspect-pre-ledger");
+        fs::write(&old.path, r#"{"accounts": {"bob": {"salt": "", "password_hash": "", "balance": 1500}}}"#).unwrap();
+        let state = Store::inspect(&old.path).unwrap();
+        assert!(state.ledger.len() == 1 && audit(&state).is_empty());
+        assert!(!old.ledger_path.exists());
+    }
+
+    #[test]
+    fn a_ledger_without_its_state_is_left_alone() {
+        let store = scratch("ledger-orphan");
+        store.register("bob", "hunter2").ok().unwrap();
+        mine(&store, "bob", 100);
+        let ledger = fs::read(&store.ledger_path).unwrap();
+        fs::remove_file(&store.path).unwrap();
+        assert!(Store::open(&store.path).is_err());
+        assert_eq!(fs::read(&store.ledger_path).unwrap(), ledger);
+    }
+
+    #[test]
+    fn wrong_admin_tokens_lock_the_console_for_a_while() {
+        let store = scratch("admin-lockout");
+        for t in 0..admin::MAX_ATTEMPTS as u64 { assert_eq!(store.check_admin(t, || false), Some(false)); }
+        assert_eq!(store.check_admin(10, || true), None);
+        // Once the first wrong token is old enough there is room for one more try
+        assert_eq!(store.check_admin(admin::ATTEMPT_WINDOW, || true), Some(true));
+        assert_eq!(store.check_admin(admin::ATTEMPT_WINDOW, || false), Some(false));
+        assert_eq!(store.check_admin(admin::ATTEMPT_WINDOW, || true), None);
+    }
+
+    #[test]
+    fn an_old_password_hash_is_upgraded_at_login() {
+        let store = scratch("old-hash");
+        let old = Account { salt: "pepper".to_string(), password_hash: hash_password("pepper", "hunter2", 0), ..Account::default() };
+        store.update(|s| { s.accounts.insert("bob".to_string(), old); Ok::<_, io::Error>(()) }).unwrap();
+        assert!(matches!(store.login("bob", "hunter3"), Err(LoginError::BadPassword)));
+        assert!(matches!(store.login("carol", "hunter2"), Err(LoginError::UnknownUser)));
+        assert!(store.login("bob", "hunter2").is_ok());
+        let acct = store.account("bob").unwrap();
+        assert_eq!(acct.rounds, 1_000);
+        assert_eq!(acct.password_hash, hash_password(&acct.salt, "hunter2", 1_000));
+        assert!(store.login("bob", "hunter2").is_ok());
+        assert!(matches!(store.register("bob", "hunter2"), Err(LoginError::Taken)));
+    }
+
+    #[test]
+    fn catalog_stock_changes_carry_over_to_items_already_sold() {
+        let catalog = |stock| format!("[[item]]\nid = 1\nname = \"tears\"\nprice = 1\nstock = {}\ndelivery = {{ kind = \"text\", text = \"t\" }}", stock);
+        let (ten, fifteen, five): (Snapshot, Snapshot, Snapshot) = (toml::from_str(&catalog(10)).unwrap(), toml::from_str(&catalog(15)).unwrap(), toml::from_str(&catalog(5)).unwrap());
+        let store = scratch("reconcile");
+        // Puts the opening stock of 10 on the books
+        store.update(|s| Ok::<_, io::Error>(s.restock(&ten.items[0], 0, 0))).unwrap().unwrap();
+        let reconcile = |before: &Snapshot, after: &Snapshot| store.update(|s| Ok::<_, io::Error>((s.reconcile_stock(before, after, 1), s.stock[&1]))).unwrap();
+        assert_eq!(reconcile(&ten, &fifteen), (1, 15));
+        assert_eq!(reconcile(&fifteen, &fifteen), (0, 15));
+        assert_eq!(reconcile(&fifteen, &five), (1, 5));
+        assert_eq!(reconcile(&fifteen, &five), (1, 0));
+        // Removed from the catalog and added back, it starts over
+        let none: Snapshot = toml::from_str("item = []").unwrap();
+        assert_eq!(reconcile(&none, &ten), (1, 10));
+        assert!(store.read(audit).is_empty());
+    }
+}
@@ -0,0 +1,264 @@
+use std::io::{BufRead, Write};
+
+use serde::Serialize;
+
+use crate::auction::Status;
+use crate::catalog::Catalog;
+use crate::clock::format_utc;
+use crate::grammar::{self, Spec, ADMIN, COMMANDS};
+use crate::limits::{Cutoff, Limits, Lines};
+use crate::session::{Bought, BundleUse, Command, Failure, Reply, Session};
+use crate::store::Store;
+
+// One line per command with the descriptions lined up
+fn command_list(specs: &[Spec]) -> String {
+    let usages: Vec<String> = specs.iter().map(Spec::usage).collect();
+    let width = usages.iter().map(String::len).max().unwrap_or(0) + 2;
+    specs.iter().zip(&usages).map(|(spec, usage)| format!("  {:<width$}- {}\n", usage, spec.summary, width = width)).collect()
+}
+
+fn banner() -> String {
+    format!("=====================================\n         Welcome to cor.shop \n=====================================\nCommands:\n{}", command_list(COMMANDS))
+}
+
+fn prompt<R: BufRead, W: Write>(lines: &mut Lines<R>, writer: &mut W, msg: &str) -> Option<String> {
+    let _ = write!(writer, "{}", msg);
+    let _ = writer.flush();
+    read(lines, writer)
+}
+
+// The next line, or `None` once the client is gone or has been cut off
+fn read<R: BufRead, W: Write>(lines: &mut Lines<R>, writer: &mut W) -> Option<String> {
+    match lines.line() {
+        Ok(line) => Some(line),
+        Err(Cutoff::Closed) => None,
+        Err(cutoff) => {
+            let _ = writeln!(writer, "\n{}", cutoff);
+            let _ = writer.flush();
+            None
+        }
+    }
+}
+
+fn login<R: BufRead, W: Write>(lines: &mut Lines<R>, writer: &mut W, session: &mut Session) -> bool {
+    for _ in 0..3 {
+        let Some(user) = prompt(lines, writer, "username: ") else { return false };
+        let Some(password) = prompt(lines, writer, "password: ") else { return false };
+        let mut result = session.execute(Command::Login { user: user.clone(), password: password.clone() });
+        // New names are only registered once the client says so, so a typo doesn't make a fresh account
+        if matches!(&result, Err(f) if f.code == "unknown_user") {
+            let Some(answer) = prompt(lines, writer, &format!("There is no account {}. Create it? [y/N]: ", user)) else { return false };
+            if !answer.eq_ignore_ascii_case("y") && !answer.eq_ignore_ascii_case("yes") { continue; }
+            result = session.execute(Command::Register { user, password });
+        }
+        render(writer, &result);
+        match result {
+            Ok(_) => return true,
+            Err(f) if f.code == "storage" => return false,
+            Err(_) => {}
+        }
+    }
+    let _ = writeln!(writer, "Too many failed attempts.");
+    false
+}
+
+fn admin_help() -> String { format!("Admin commands:\n{}", command_list(ADMIN)) }
+
+fn render_bought<W: Write>(writer: &mut W, bought: &Bought) {
+    for d in &bought.deliveries {
+        match &d.content {
+            Some(content) => { let _ = write!(writer, "{}", content); }
+            None => { let _ = writeln!(writer, "Delivery of {} failed, please contact an admin.", d.name); }
+        }
+    }
+}
+
+// Bundle prices are already spread over the lines above, this just says which ones were used
+fn render_bundles<W: Write>(writer: &mut W, bundles: &[BundleUse]) {
+    for b in bundles {
+        let _ = writeln!(writer, "  priced as {} x {} bundle @ {}", b.qty, b.name, b.price);
+    }
+}
+
+// The name a value has on the JSON protocol, so both front-ends use the same words
+fn wire_name<T: Serialize>(value: &T) -> String {
+    serde_json::to_value(value).ok().and_then(|v| v.as_str().map(str::to_string)).unwrap_or_default()
+}
+
+pub(crate) fn render<W: Write>(writer: &mut W, result: &Result<Reply, Failure>) {
+    let reply = match result {
+        Ok(reply) => reply,
+        Err(f) => { let _ = writeln!(writer, "{}", f.message); return; }
+    };
+    let _ = match reply {
+        Reply::LoggedIn { user, created: true, .. } => writeln!(writer, "Welcome to cor.shop, {}! Your account has been created.", user),
+        Reply::LoggedIn { user, created: false, notices } => {
+            let _ = writeln!(writer, "Welcome back, {}.", user);
+            if !notices.is_empty() { let _ = writeln!(writer, "While you were away:"); }
+            for n in notices {
+                let _ = writeln!(writer, "  {}  {}", format_utc(n.time), n.message);
+            }
+            Ok(())
+        }
+        Reply::Items { items, bundles } => {
+            // List table of our items
+            let _ = writeln!(writer, "ID  |   PRICE | STOCK | NAME");
+            let _ = writeln!(writer, "----+---------+-------+------------------------------");
+            for it in items {
+                let stock = it.stock.map_or("-".to_string(), |n| n.to_string());
+                let _ = writeln!(writer, "{:<3} | {:>7} | {:>5} | {}", it.id, it.price, stock, it.name);
+                for t in &it.tiers {
+                    let _ = writeln!(writer, "    | {:>7} |       |   each when buying {}+", t.price, t.min);
+                }
+            }
+            if !bundles.is_empty() { let _ = writeln!(writer, "Bundles, applied at checkout when your cart has everything in one:"); }
+            for b in bundles {
+                let parts = b.items.iter().map(|p| format!("{} x {}", p.qty, p.name)).collect::<Vec<_>>().join(" + ");
+                let worth = b.worth.map_or(String::new(), |w| format!(" instead of {}", w));
+                let _ = writeln!(writer, "  #{} {}: {} for {} corns{}", b.id, b.name, parts, b.price, worth);
+            }
+            Ok(())
+        }
+        Reply::Info { item } => {
+            let _ = writeln!(writer, "{} ({} corns)", item.name, item.price);
+            for t in &item.tiers {
+                let _ = writeln!(writer, "{} corns each when buying {} or more", t.price, t.min);
+            }
+            if item.final_sale { let _ = writeln!(writer, "Final sale: no refunds or sell-back."); }
+            if item.description.is_empty() { Ok(()) } else { writeln!(writer, "{}", item.description) }
+        }
+        // Show the current balance in corns
+        Reply::Balance { balance } => writeln!(writer, "Balance: {} corns", balance),
+        Reply::Inventory { items } if items.is_empty() => writeln!(writer, "You don't own anything yet."),
+        Reply::Inventory { items } => {
+            let _ = writeln!(writer, "ID  |     QTY | NAME");
+            let _ = writeln!(writer, "----+---------+------------------------------");
+            for it in items {
+                let _ = writeln!(writer, "{:<3} | {:>7} | {}", it.id, it.qty, it.name.as_deref().unwrap_or("(discontinued)"));
+            }
+            Ok(())
+        }
+        Reply::Ledger { entries } if entries.is_empty() => writeln!(writer, "No transactions yet."),
+        Reply::Ledger { entries } => {
+            let _ = writeln!(writer, "   # | TIME (UTC)          | KIND     |      CHANGE |     BALANCE | MEMO");
+            let _ = writeln!(writer, "-----+---------------------+----------+-------------+-------------+------------------------------");
+            for e in entries {
+                let change = if e.paid > e.received { format!("-{}", e.paid.minus(e.received).unwrap_or_default()) } else { format!("+{}", e.received.minus(e.paid).unwrap_or_default()) };
+                let _ = writeln!(writer, "{:>4} | {} | {:<8} | {:>11} | {:>11} | {}", e.id, format_utc(e.time), wire_name(&e.kind), change, e.balance, e.memo);
+            }
+            Ok(())
+        }
+        Reply::Bought(bought) => {
+            for line in &bought.lines {
+                let _ = writeln!(writer, "Purchased {} x {} for {} corns.", line.qty, line.name, bought.total);
+            }
+            if let Some(code) = &bought.coupon {
+                let _ = writeln!(writer, "Coupon {} saved you {} corns.", code, bought.discount);
+            }
+            let _ = writeln!(writer, "Order #{}.", bought.order);
+            render_bought(writer, bought);
+            Ok(())
+        }
+        Reply::CheckedOut(bought) => {
+            let _ = writeln!(writer, "Receipt for order #{}:", bought.order);
+            for line in &bought.lines {
+                let _ = writeln!(writer, "{:>7} x {} @ {} = {}", line.qty, line.name, line.price, line.cost);
+            }
+            render_bundles(writer, &bought.bundles);
+            if let Some(code) = &bought.coupon {
+                let _ = writeln!(writer, "Coupon {}: -{}", code, bought.discount);
+            }
+            let _ = writeln!(writer, "Total: {} corns", bought.total);
+            render_bought(writer, bought);
+            Ok(())
+        }
+        Reply::Refunded { order, amount, balance } => writeln!(writer, "Refunded order #{}: {} corns back. Balance: {} corns.", order, amount, balance),
+        Reply::Sold { name, qty, amount, balance, .. } => writeln!(writer, "Sold {} x {} for {} corns. Balance: {} corns.", qty, name, amount, balance),
+        Reply::Auctions { auctions } if auctions.is_empty() => writeln!(writer, "No auctions right now."),
+        Reply::Auctions { auctions } => {
+            let _ = writeln!(writer, "  # | STATUS   | ENDS (UTC)          |    HIGH BID |     MIN BID | LOT");
+            let _ = writeln!(writer, "----+----------+---------------------+-------------+-------------+------------------------------");
+            for a in auctions {
+                let high = a.high_bid.map_or("-".to_string(), |b| b.to_string());
+                let min = a.min_bid.map_or("-".to_string(), |b| b.to_string());
+                let yours = match (a.yours, a.status) { (false, _) => "", (true, Status::Ended) => " (you won)", (true, _) => " (your bid)" };
+                let _ = writeln!(writer, "{:>3} | {:<8} | {} | {:>11} | {:>11} | {} x {}{}", a.id, wire_name(&a.status), format_utc(a.ends_at), high, min, a.qty, a.name, yours);
+            }
+            Ok(())
+        }
+        Reply::BidPlaced { auction, amount, balance } => writeln!(writer, "You bid {} corns on auction #{}, held until it ends. Balance: {} corns.", amount, auction, balance),
+        Reply::Gave { user, amount, balance } => writeln!(writer, "Gave {} corns to {}. Balance: {} corns.", amount, user, balance),
+        Reply::Gifted { user, name, qty, .. } => writeln!(writer, "Gifted {} x {} to {}.", qty, name, user),
+        Reply::CartUpdated { qty, name: Some(name), .. } => writeln!(writer, "Cart now has {} x {}.", qty, name),
+        Reply::CartUpdated { id, qty: 0, name: None } => writeln!(writer, "Removed item {} from your cart.", id),
+        Reply::CartUpdated { id, qty, name: None } => writeln!(writer, "Cart now has {} of item {}.", qty, id),
+        Reply::Cart { lines, .. } if lines.is_empty() => writeln!(writer, "Your cart is empty."),
+        Reply::Cart { lines, bundles, total } => {
+            for l in lines {
+                let cost = l.cost.map_or("overflow".to_string(), |c| c.to_string());
+                let _ = match (&l.name, l.price) {
+                    (Some(name), Some(price)) => writeln!(writer, "{:>7} x {} @ {} = {}", l.qty, name, price, cost),
+                    _ => writeln!(writer, "{:>7} x item {} (no longer sold)", l.qty, l.id),
+                };
+            }
+            render_bundles(writer, bundles);
+            match total {
+                Some(total) => writeln!(writer, "Total: {} corns", total),
+                None => writeln!(writer, "Total: that is more corns than exist"),
+            }
+        }
+        Reply::CouponApplied { code, discount } => writeln!(writer, "Coupon {} ({}) will be applied at checkout.", code, discount),
+        Reply::Challenge { prefix, difficulty, reward } => {
+            let _ = writeln!(writer, "Find a string X so that sha256(\"{}\" + X) starts with {} zero bits.", prefix, difficulty);
+            writeln!(writer, "Then send `mine X` to earn {} corns.", reward)
+        }
+        Reply::Mined { reward, balance } => writeln!(writer, "Mined {} corns! Balance: {} corns. The next one will be harder.", reward, balance),
+        Reply::AdminUnlocked => write!(writer, "Admin console unlocked.\n{}", admin_help()),
+        Reply::Restocked { name, stock, .. } => writeln!(writer, "{} now has {} in stock.", name, stock),
+        Reply::PriceChanged { name, old, new, .. } => writeln!(writer, "{} now costs {} corns (was {}).", name, new, old),
+        Reply::ItemAdded { id, name } => writeln!(writer, "Now selling {} as item {}.", name, id),
+        Reply::ItemRemoved { id, name } => writeln!(writer, "No longer selling {} (item {}).", name, id),
+        Reply::Granted { user, amount, balance } => writeln!(writer, "Granted {} corns to {}. Their balance: {} corns.", amount, user, balance),
+        Reply::Sessions { sessions } if sessions.is_empty() => writeln!(writer, "Nobody is logged in."),
+        Reply::Sessions { sessions } => {
+            let _ = writeln!(writer, "USER                             | SESSIONS |     BALANCE");
+            let _ = writeln!(writer, "---------------------------------+----------+-------------");
+            for o in sessions {
+                let _ = writeln!(writer, "{:<32} | {:>8} | {:>11}", o.user, o.sessions, o.balance);
+            }
+            Ok(())
+        }
+        Reply::Help => writeln!(writer, "{}", banner()),
+        Reply::Usage { usage, summary, params, .. } => {
+            let _ = writeln!(writer, "{} - {}", usage, summary);
+            for p in params {
+                let _ = writeln!(writer, "  {:<10} {}", p.name, p.about);
+            }
+            Ok(())
+        }
+        Reply::Bye => writeln!(writer, "bye!"),
+    };
+}
+
+/// The interactive text shop: a banner, a login, then one command per `> ` prompt.
+pub fn shop<R: BufRead, W: Write>(reader: R, mut writer: W, store: &Store, catalog: &Catalog, limits: Limits) {
+    let mut session = Session::new(store, catalog);
+    let mut lines = Lines::new(reader, limits);
+    let _ = writeln!(writer, "{}", banner());
+    if !login(&mut lines, &mut writer, &mut session) { return; }
+    render(&mut writer, &session.execute(Command::Balance));
+
+    loop {
+        // Print prompt
+        let _ = write!(writer, "> ");
+        let _ = writer.flush();
+
+        // Read user input
+        let Some(line) = read(&mut lines, &mut writer) else { break };
+        if line.is_empty() { continue; }
+
+        let result = grammar::parse_text(&line).and_then(|cmd| session.execute(cmd));
+        render(&mut writer, &result);
+        if let Ok(Reply::Bye) = result { break; }
+    }
+}
@@ -0,0 +1,169 @@
+use std::fmt;
+use std::io;
+
+use serde::Deserialize;
+
+use crate::ledger::{user_book, Kind, Leg, Ledger};
+use crate::money::{Corns, MoneyError};
+use crate::store::{State, Store};
+
+const DAY: u64 = 24 * 60 * 60;
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct TransferConfig {
+    /// Corns a user may give away, and be given, per UTC day.
+    pub daily_corns: Corns,
+    /// Items a user may gift, and be gifted, per UTC day.
+    pub daily_items: u64,
+}
+
+impl Default for TransferConfig {
+    fn default() -> TransferConfig { TransferConfig { daily_corns: Corns::new(100_000), daily_items: 10 } }
+}
+
+pub enum TransferError {
+    ToSelf,
+    UnknownUser(String),
+    Insufficient { need: Corns, have: Corns },
+    NotOwned { name: String, have: u64 },
+    CornLimit { left: Corns },
+    ItemLimit { left: u64 },
+    // Caps what one user can collect, so corns mined on throwaway accounts can't be pooled
+    RecipientCornLimit { user: String, left: Corns },
+    RecipientItemLimit { user: String, left: u64 },
+    Money(MoneyError),
+    Io(io::Error),
+}
+
+impl From<io::Error> for TransferError { fn from(e: io::Error) -> TransferError { TransferError::Io(e) } }
+
+impl From<MoneyError> for TransferError { fn from(e: MoneyError) -> TransferError { TransferError::Money(e) } }
+
+impl fmt::Display for TransferError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransferError::ToSelf => write!(f, "You can't send things to yourself."),
+            TransferError::UnknownUser(user) => write!(f, "There is no user {}.", user),
+            TransferError::Insufficient { need, have } => write!(f, "Insufficient balance. Need {}, have {}.", need, have),
+            TransferError::NotOwned { name, have } => write!(f, "You only have {} x {} to give.", have, name),
+            TransferError::CornLimit { left } => write!(f, "That is over your daily limit. You can give {} more corns today.", left),
+            TransferError::ItemLimit { left } => write!(f, "That is over your daily limit. You can gift {} more items today.", left),
+            TransferError::RecipientCornLimit { user, left } => write!(f, "That is over {}'s daily limit. They can be given {} more corns today.", user, left),
+            TransferError::RecipientItemLimit { user, left } => write!(f, "That is over {}'s daily limit. They can be gifted {} more items today.", user, left),
+            TransferError::Money(e) => write!(f, "Can't send that: {}.", e),
+            TransferError::Io(_) => write!(f, "Could not save the transfer, try again later."),
+        }
+    }
+}
+
+/// Corns and items moved between users since the start of the current UTC day, as `(sent, received)` by `book`.
+fn moved_today(ledger: &Ledger, book: &str, now: u64) -> ((u128, u128), (u128, u128)) {
+    let midnight = now - now % DAY;
+    let (mut sent, mut received) = ((0, 0), (0, 0));
+    for tx in ledger.iter().rev().take_while(|tx| tx.time >= midnight) {
+        if !matches!(tx.kind, Kind::Give | Kind::Gift) { continue; }
+        for leg in &tx.legs {
+            let (from, to, corns, items) = match leg {
+                Leg::Corns { from, to, amount } => (from, to, amount.get() as u128, 0),
+                Leg::Item { from, to, qty, .. } => (from, to, 0, *qty as u128),
+            };
+            if from == book { sent.0 += corns; sent.1 += items; }
+            if to == book { received.0 += corns; received.1 += items; }
+        }
+    }
+    (sent, received)
+}
+
+fn check_recipient(s: &State, from: &str, to: &str) -> Result<(), TransferError> {
+    if from == to { return Err(TransferError::ToSelf); }
+    if !s.accounts.contains_key(to) { return Err(TransferError::UnknownUser(to.to_string())); }
+    Ok(())
+}
+
+/// Moves `amount` corns from `from` to `to` in one transaction. Returns the sender's new balance.
+pub fn give(store: &Store, from: &str, to: &str, amount: Corns, config: &TransferConfig, now: u64) -> Result<Corns, TransferError> {
+    store.update(|s| {
+        check_recipient(s, from, to)?;
+        let limit = config.daily_corns.get() as u128;
+        let ((sent, _), _) = moved_today(&s.ledger, &user_book(from), now);
+        let left = limit.saturating_sub(sent) as u64;
+        if amount.get() > left { return Err(TransferError::CornLimit { left: Corns::new(left) }); }
+        let (_, (received, _)) = moved_today(&s.ledger, &user_book(to), now);
+        let left = limit.saturating_sub(received) as u64;
+        if amount.get() > left { return Err(TransferError::RecipientCornLimit { user: to.to_string(), left: Corns::new(left) }); }
+
+        let sender = s.accounts.get_mut(from).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such account"))?;
+        if sender.balance < amount { return Err(TransferError::Insufficient { need: amount, have: sender.balance }); }
+        sender.balance = sender.balance.minus(amount)?;
+        let balance = sender.balance;
+        let recipient = s.accounts.get_mut(to).ok_or_else(|| TransferError::UnknownUser(to.to_string()))?;
+        recipient.balance = recipient.balance.plus(amount)?;
+
+        let leg = Leg::Corns { from: user_book(from), to: user_book(to), amount };
+        s.record(Kind::Give, now, format!("{} gave {} corns to {}", from, amount, to), vec![leg]);
+        s.notify(to, now, format!("{} gave you {} corns.", from, amount));
+        Ok(balance)
+    })
+}
+
+/// Moves `qty` units of item `id` from `from`'s inventory to `to`'s in one transaction.
+pub fn gift(store: &Store, from: &str, to: &str, (id, qty): (u32, u64), name: &str, config: &TransferConfig, now: u64) -> Result<(), TransferError> {
+    store.update(|s| {
+        check_recipient(s, from, to)?;
+        let limit = config.daily_items as u128;
+        let ((_, sent), _) = moved_today(&s.ledger, &user_book(from), now);
+        let left = limit.saturating_sub(sent) as u64;
+        if qty > left { return Err(TransferError::ItemLimit { left }); }
+        let (_, (_, received)) = moved_today(&s.ledger, &user_book(to), now);
+        let left = limit.saturating_sub(received) as u64;
+        if qty > left { return Err(TransferError::RecipientItemLimit { user: to.to_string(), left }); }
+
+        let sender = s.accounts.get_mut(from).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such account"))?;
+        let have = sender.owned.get(&id).copied().unwrap_or(0);
+        if have < qty { return Err(TransferError::NotOwned { name: name.to_string(), have }); }
+        if have == qty { sender.owned.remove(&id); } else { sender.owned.insert(id, have - qty); }
+        let recipient = s.accounts.get_mut(to).ok_or_else(|| TransferError::UnknownUser(to.to_string()))?;
+        let owned = recipient.owned.entry(id).or_insert(0);
+        *owned = owned.checked_add(qty).ok_or(MoneyError::Overflow)?;
+
+        let leg = Leg::Item { id, qty, from: user_book(from), to: user_book(to) };
+        s.record(Kind::Gift, now, format!("{} gifted {} x {} to {}", from, qty, name, to), vec![leg]);
+        s.notify(to, now, format!("{} gifted you {} x {}.", from, qty, name));
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::ADMIN;
+    use crate::store::scratch;
+
+    const CONFIG: TransferConfig = TransferConfig { daily_corns: Corns::new(5000), daily_items: 2 };
+
+    fn grant(store: &Store, user: &str, amount: u64) {
+        store.register(user, "hunter2").ok().unwrap();
+        store.update(|s| {
+            s.accounts.get_mut(user).unwrap().balance = Corns::new(amount);
+            let leg = Leg::Corns { from: ADMIN.to_string(), to: user_book(user), amount: Corns::new(amount) };
+            s.record(Kind::Grant, 0, "grant".to_string(), vec![leg]);
+            Ok::<_, io::Error>(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn the_daily_limit_also_caps_what_a_user_is_given() {
+        let store = scratch("receive-limit");
+        grant(&store, "alice", 10_000);
+        grant(&store, "carol", 10_000);
+        grant(&store, "bob", 0);
+        give(&store, "alice", "bob", Corns::new(3000), &CONFIG, 100).ok().unwrap();
+        match give(&store, "carol", "bob", Corns::new(2500), &CONFIG, 200) {
+            Err(TransferError::RecipientCornLimit { user, left }) => assert_eq!((user.as_str(), left), ("bob", Corns::new(2000))),
+            _ => panic!("bob was given more than the daily limit"),
+        }
+        assert_eq!(give(&store, "carol", "bob", Corns::new(2000), &CONFIG, 300).ok(), Some(Corns::new(8000)));
+        assert_eq!(give(&store, "carol", "bob", Corns::new(2500), &CONFIG, DAY + 1).ok(), Some(Corns::new(5500)));
+    }
+}
@@ -0,0 +1,209 @@
+use std::io;
+use std::time::Duration;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Cell, Clear, List, ListItem, Paragraph, Row, Table, TableState, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::catalog::Catalog;
+use crate::money::Corns;
+use crate::session::{CartLine, Command, Failure, Listing, Reply, Session};
+use crate::store::Store;
+use crate::text;
+
+const KEYS: &str = "↑/↓ select  enter add to cart  - take out  b buy now  c checkout  r refresh  q quit";
+
+enum Field { User, Password }
+
+enum Screen {
+    // `register` is set once a login finds no such account, so the next enter creates it
+    Login { user: String, password: String, field: Field, register: bool },
+    Shop,
+}
+
+/// The full-screen storefront. Every key press becomes a `Command` for the same `Session` the
+/// line-based front-ends use, and replies are shown exactly as the `> ` prompt would print them.
+struct App<'a> {
+    session: Session<'a>,
+    screen: Screen,
+    user: String,
+    items: Vec<Listing>,
+    table: TableState,
+    cart: Vec<CartLine>,
+    cart_total: Option<Corns>,
+    balance: Corns,
+    // What the last command printed, deliveries included
+    messages: String,
+    quit: bool,
+}
+
+/// Runs the storefront on the current terminal until the user quits.
+pub fn shop(store: &Store, catalog: &Catalog) -> io::Result<()> {
+    let mut terminal = ratatui::init();
+    let mut app = App {
+        session: Session::new(store, catalog),
+        screen: Screen::Login { user: String::new(), password: String::new(), field: Field::User, register: false },
+        user: String::new(),
+        items: Vec::new(),
+        table: TableState::default().with_selected(Some(0)),
+        cart: Vec::new(),
+        cart_total: Some(Corns::ZERO),
+        balance: Corns::ZERO,
+        messages: "Log in or pick a username to register.".to_string(),
+        quit: false,
+    };
+    let result = app.run(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+impl App<'_> {
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        while !self.quit {
+            terminal.draw(|f| self.draw(f))?;
+            // Wake up now and then so gifts, grants and auction wins show up without a key press
+            if !event::poll(Duration::from_secs(2))? {
+                self.refresh();
+                continue;
+            }
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press { self.key(key.code); }
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs `cmd`, shows what it printed and refreshes the panes it may have changed.
+    fn execute(&mut self, cmd: Command) {
+        let result = self.session.execute(cmd);
+        self.show(&result);
+        if matches!(result, Ok(Reply::Bye)) { self.quit = true; }
+        self.refresh();
+    }
+
+    fn show(&mut self, result: &Result<Reply, Failure>) {
+        let mut out = Vec::new();
+        text::render(&mut out, result);
+        self.messages = String::from_utf8_lossy(&out).trim_end().to_string();
+    }
+
+    // Quietly reloads the products, cart and balance
+    fn refresh(&mut self) {
+        if matches!(self.screen, Screen::Login { .. }) { return; }
+        if let Ok(Reply::Items { items, .. }) = self.session.execute(Command::List) { self.items = items; }
+        if let Ok(Reply::Cart { lines, total, .. }) = self.session.execute(Command::CartShow) { (self.cart, self.cart_total) = (lines, total); }
+        if let Ok(Reply::Balance { balance }) = self.session.execute(Command::Balance) { self.balance = balance; }
+        let last = self.items.len().saturating_sub(1);
+        self.table.select(Some(self.table.selected().unwrap_or(0).min(last)));
+    }
+
+    fn selected(&self) -> Option<u32> { self.table.selected().and_then(|i| self.items.get(i)).map(|it| it.id) }
+
+    fn key(&mut self, code: KeyCode) {
+        if let Screen::Login { user, password, field, register } = &mut self.screen {
+            if code != KeyCode::Enter { *register = false; }
+            let input = match field { Field::User => &mut *user, Field::Password => &mut *password };
+            match code {
+                KeyCode::Esc => self.quit = true,
+                KeyCode::Char(c) => input.push(c),
+                KeyCode::Backspace => { input.pop(); }
+                KeyCode::Tab | KeyCode::Up | KeyCode::Down => *field = match field { Field::User => Field::Password, Field::Password => Field::User },
+                KeyCode::Enter if matches!(field, Field::User) => *field = Field::Password,
+                KeyCode::Enter => {
+                    let (user, password, register) = (user.clone(), password.clone(), *register);
+                    self.login(user, password, register);
+                }
+                _ => {}
+            }
+            return;
+        }
+        match (code, self.selected()) {
+            (KeyCode::Char('q') | KeyCode::Esc, _) => self.execute(Command::Quit),
+            (KeyCode::Up | KeyCode::Char('k'), _) => self.table.select_previous(),
+            (KeyCode::Down | KeyCode::Char('j'), _) if self.table.selected().is_some_and(|i| i + 1 < self.items.len()) => self.table.select_next(),
+            (KeyCode::Enter | KeyCode::Char('+') | KeyCode::Char('a'), Some(id)) => self.execute(Command::CartAdd { id, qty: 1 }),
+            (KeyCode::Char('-') | KeyCode::Char('x'), Some(id)) => self.execute(Command::CartRemove { id, qty: Some(1) }),
+            (KeyCode::Char('b'), Some(id)) => self.execute(Command::Buy { id, qty: 1, coupon: None }),
+            (KeyCode::Char('c'), _) => self.execute(Command::Checkout),
+            (KeyCode::Char('r'), _) => self.refresh(),
+            _ => {}
+        }
+    }
+
+    fn login(&mut self, user: String, password: String, register: bool) {
+        let cmd = if register { Command::Register { user: user.clone(), password } } else { Command::Login { user: user.clone(), password } };
+        let result = self.session.execute(cmd);
+        self.show(&result);
+        let Screen::Login { password, register, .. } = &mut self.screen else { return };
+        match result {
+            Ok(_) => {
+                self.user = user;
+                self.screen = Screen::Shop;
+                self.refresh();
+            }
+            Err(Failure { code: "storage", .. }) => self.quit = true,
+            Err(Failure { code: "unknown_user", .. }) => {
+                *register = true;
+                self.messages = format!("There is no account {}. Press enter again to create it.", user);
+            }
+            Err(_) => password.clear(),
+        }
+    }
+
+    fn draw(&mut self, f: &mut Frame) {
+        if let Screen::Login { user, password, field, register } = &self.screen {
+            let area = centered(f.area(), 50, 9);
+            let cursor = |on| if on { Style::new().add_modifier(Modifier::REVERSED) } else { Style::new() };
+            let lines = vec![
+                Line::from(vec!["Username: ".into(), Span::styled(format!("{} ", user), cursor(matches!(field, Field::User)))]),
+                Line::from(vec!["Password: ".into(), Span::styled(format!("{} ", "*".repeat(password.chars().count())), cursor(matches!(field, Field::Password)))]),
+                Line::default(),
+                Line::from(self.messages.as_str()).fg(Color::Yellow),
+                Line::default(),
+                Line::from(if *register { "tab switch field  enter create account  esc quit" } else { "tab switch field  enter log in  esc quit" }).dim(),
+            ];
+            f.render_widget(Clear, area);
+            f.render_widget(Paragraph::new(lines).block(Block::bordered().title(" cor.shop ")), area);
+            return;
+        }
+
+        let [header, main, messages, keys] = Layout::vertical([Constraint::Length(1), Constraint::Min(6), Constraint::Length(8), Constraint::Length(1)]).areas(f.area());
+        let [products, cart] = Layout::horizontal([Constraint::Percentage(62), Constraint::Percentage(38)]).areas(main);
+
+        let status = format!(" cor.shop  |  {}  |  Balance: {} corns", self.user, self.balance);
+        f.render_widget(Paragraph::new(status).style(Style::new().bg(Color::Blue).fg(Color::White).bold()), header);
+
+        let rows = self.items.iter().map(|it| Row::new(vec![
+            Cell::from(it.id.to_string()),
+            Cell::from(it.name.clone()),
+            Cell::from(it.price.to_string()),
+            Cell::from(it.stock.map_or("-".to_string(), |n| n.to_string())),
+        ]));
+        let table = Table::new(rows, [Constraint::Length(4), Constraint::Min(10), Constraint::Length(10), Constraint::Length(6)])
+            .header(Row::new(vec!["ID", "NAME", "PRICE", "STOCK"]).bold())
+            .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+            .block(Block::bordered().title(" Products "));
+        f.render_stateful_widget(table, products, &mut self.table);
+
+        let mut lines: Vec<ListItem> = self.cart.iter().map(|l| {
+            let cost = l.cost.map_or("overflow".to_string(), |c| c.to_string());
+            ListItem::new(format!("{} x {} = {}", l.qty, l.name.as_deref().unwrap_or("(no longer sold)"), cost))
+        }).collect();
+        if lines.is_empty() { lines.push(ListItem::new("Your cart is empty.").dim()); }
+        let total = self.cart_total.map_or(" Total: overflow ".to_string(), |t| format!(" Total: {} corns ", t));
+        f.render_widget(List::new(lines).block(Block::bordered().title(" Cart ").title_bottom(total)), cart);
+
+        let messages_block = Block::new().borders(Borders::ALL).title(" Messages ");
+        f.render_widget(Paragraph::new(self.messages.as_str()).wrap(Wrap { trim: false }).block(messages_block), messages);
+        f.render_widget(Paragraph::new(KEYS).dim(), keys);
+    }
+}
+
+fn centered(area: Rect, width: u16, height: u16) -> Rect {
+    let [_, row, _] = Layout::vertical([Constraint::Fill(1), Constraint::Length(height), Constraint::Fill(1)]).areas(area);
+    let [_, rect, _] = Layout::horizontal([Constraint::Fill(1), Constraint::Length(width), Constraint::Fill(1)]).areas(row);
+    rect
+}
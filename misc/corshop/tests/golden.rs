@@ -0,0 +1,64 @@
+//! Replays every transcript in `tests/golden` and compares what the shop printed with the
+//! `.golden` file next to it. `.txt` transcripts use the text protocol and `.jsonl` ones the JSON
+//! protocol. After an intended change, rerun with `UPDATE_GOLDEN=1` and review the diff.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn transcripts(dir: &Path) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir).unwrap().map(|e| e.unwrap().path())
+        .filter(|p| matches!(p.extension().and_then(|e| e.to_str()), Some("txt" | "jsonl")))
+        .collect();
+    paths.sort();
+    paths
+}
+
+fn replay(dir: &Path, transcript: &Path) -> String {
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_cor-shop"));
+    cmd.arg("replay").arg(transcript).env("CORSHOP_CATALOG", dir.join("catalog.toml")).env_remove("CORSHOP_ADMIN_TOKEN");
+    if transcript.extension().is_some_and(|e| e == "jsonl") { cmd.arg("--json"); }
+    let out = cmd.output().unwrap();
+    assert!(out.status.success(), "{}: {}", transcript.display(), String::from_utf8_lossy(&out.stderr));
+    String::from_utf8(out.stdout).unwrap()
+}
+
+// The first line that differs, which is usually enough to see what changed
+fn first_difference(want: &str, got: &str) -> String {
+    let (mut want, mut got) = (want.lines(), got.lines());
+    for n in 1.. {
+        match (want.next(), got.next()) {
+            (None, None) => break,
+            (w, g) if w == g => continue,
+            (w, g) => return format!("line {}:\n  want: {}\n  got:  {}", n, w.unwrap_or("<end>"), g.unwrap_or("<end>")),
+        }
+    }
+    String::new()
+}
+
+#[test]
+fn transcripts_match_golden_output() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let update = env::var_os("UPDATE_GOLDEN").is_some();
+    let mut failures = Vec::new();
+    for transcript in transcripts(&dir) {
+        let got = replay(&dir, &transcript);
+        let golden = transcript.with_extension(format!("{}.golden", transcript.extension().unwrap().to_str().unwrap()));
+        if update {
+            fs::write(&golden, &got).unwrap();
+            continue;
+        }
+        let want = fs::read_to_string(&golden).unwrap_or_default();
+        if want != got { failures.push(format!("{}: {}", transcript.display(), first_difference(&want, &got))); }
+    }
+    assert!(failures.is_empty(), "output changed, rerun with UPDATE_GOLDEN=1 if that was intended\n{}", failures.join("\n"));
+}
+
+#[test]
+fn replays_are_deterministic() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    for transcript in transcripts(&dir) {
+        assert_eq!(replay(&dir, &transcript), replay(&dir, &transcript), "{}", transcript.display());
+    }
+}
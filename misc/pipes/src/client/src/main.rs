@@ -1,39 +1,85 @@
-use anyhow::{Context, Result};
-use prost::Message as ProstMessage;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, bail};
 use ratatui::crossterm::event::{self, Event, KeyCode};
 use ratatui::crossterm::terminal::{disable_raw_mode, enable_raw_mode};
 use ratatui::text::Line;
 use ratatui::{Terminal, backend::CrosstermBackend, style::*, widgets::*};
 use shared::{
-    ClientMessage, ServerMessage,
+    ClientMessage, Fifo, FrameDecoder, MacKey, ServerMessage, SessionPaths, Signer, Transport,
+    TransportKind, check_owned,
     client_message::{PlayerMove, player_move::Direction},
+    encode_frame, session_root,
 };
 use tokio::fs::OpenOptions;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::unix::pipe::{self, Receiver, Sender};
+use tokio::net::UnixStream;
+use tokio::net::unix::pipe;
+
+struct Args {
+    dir: Option<PathBuf>,
+    maze: usize,
+    transport: TransportKind,
+}
+
+impl Args {
+    fn parse() -> Result<Self> {
+        let mut args = Args {
+            dir: None,
+            maze: 0,
+            transport: TransportKind::Fifo,
+        };
+        let mut argv = std::env::args().skip(1);
+        while let Some(arg) = argv.next() {
+            let mut value = || argv.next().with_context(|| format!("{arg} needs a value"));
+            match arg.as_str() {
+                "--dir" => args.dir = Some(value()?.into()),
+                "--maze" => args.maze = value()?.parse().context("--maze takes a number")?,
+                "--transport" => args.transport = value()?.parse().map_err(anyhow::Error::msg)?,
+                _ => bail!(
+                    "unknown argument {arg}, usage: client [--dir <path>] [--maze <n>] \
+                     [--transport fifo|socket]"
+                ),
+            }
+        }
+        Ok(args)
+    }
+}
 
-async fn load_key() -> Result<[u8; 16]> {
+async fn load_key(keypath: &Path) -> Result<[u8; 16]> {
     let mut buf = [0; 16];
     let mut file = OpenOptions::new()
         .read(true)
         .create(false)
-        .open(shared::KEY_LOCATION)
+        .open(keypath)
         .await
         .context("reading keyfile")?;
     file.read_exact(&mut buf).await?;
     Ok(buf)
 }
 
-async fn open_pipe() -> Result<(Sender, Receiver)> {
+fn open_pipe(paths: &SessionPaths) -> Result<Fifo> {
     let sender = pipe::OpenOptions::new()
         .read_write(true)
-        .open_sender(shared::PIPE_IN_LOCATION)
+        .open_sender(paths.pipe_in())
         .context("opening named pipe for sending")?;
     let receiver = pipe::OpenOptions::new()
         .read_write(true)
-        .open_receiver(shared::PIPE_OUT_LOCATION)
+        .open_receiver(paths.pipe_out())
         .context("opening named pipe for receiving")?;
-    Ok((sender, receiver))
+    Ok(Fifo::new(receiver, sender))
+}
+
+async fn connect(kind: TransportKind, paths: &SessionPaths) -> Result<Box<dyn Transport>> {
+    Ok(match kind {
+        TransportKind::Fifo => Box::new(open_pipe(paths)?),
+        TransportKind::Socket => Box::new(
+            UnixStream::connect(paths.socket())
+                .await
+                .context("connecting to the server socket")?,
+        ),
+    })
 }
 
 fn render_maze_ui<B: ratatui::backend::Backend>(
@@ -57,26 +103,50 @@ fn render_maze_ui<B: ratatui::backend::Backend>(
     Ok(())
 }
 
-async fn read_server_msg(receiver: &mut Receiver) -> Result<Option<ServerMessage>> {
+/// Returns every complete message the server has sent since the last call, oldest first.
+async fn read_server_msgs(
+    transport: &mut dyn Transport,
+    decoder: &mut FrameDecoder,
+) -> Result<Vec<ServerMessage>> {
     let mut buf = vec![0; 4096 * 4];
-    let n = match receiver.try_read(&mut buf) {
-        Ok(0) => return Ok(None),
-        Ok(n) => n,
-        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(None),
-        Err(e) => Err(e)?,
-    };
-    Ok(Some(ServerMessage::decode(&buf[..n])?))
+    loop {
+        match transport.try_read(&mut buf) {
+            Ok(0) => bail!("the server closed the connection"),
+            Ok(n) => decoder.push(&buf[..n]),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+            Err(e) => Err(e)?,
+        }
+    }
+    let mut messages = Vec::new();
+    while let Some(msg) = decoder.next_frame()? {
+        messages.push(msg);
+    }
+    Ok(messages)
 }
 
-async fn send_message(sender: &mut Sender, message: ClientMessage) -> Result<()> {
-    sender.write_all(&message.encode_to_vec()).await?;
+async fn send_message(
+    transport: &mut dyn Transport,
+    signer: &mut Signer,
+    message: ClientMessage,
+) -> Result<()> {
+    transport
+        .write_all(&encode_frame(&signer.sign(&message)))
+        .await?;
     Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let protocol_key = load_key().await?.to_vec();
-    let (mut pipe_sender, mut pipe_receiver) = open_pipe().await?;
+    let args = Args::parse()?;
+    let root = session_root(args.dir);
+    let paths = SessionPaths::new(&root, args.maze);
+    // Only trust a key the server left in directories of its own
+    check_owned(&root).with_context(|| format!("refusing {}", root.display()))?;
+    check_owned(&paths.dir).with_context(|| format!("refusing {}", paths.dir.display()))?;
+    // The server only accepts increasing sequence numbers, so start above anything a previous run sent
+    let start = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u64;
+    let mut signer = Signer::new(MacKey::derive(&load_key(&paths.key()).await?), start);
+    let mut transport = connect(args.transport, &paths).await?;
 
     enable_raw_mode()?;
     let mut stdout = std::io::stdout();
@@ -85,18 +155,19 @@ async fn main() -> Result<()> {
     terminal.clear()?;
 
     send_message(
-        &mut pipe_sender,
+        transport.as_mut(),
+        &mut signer,
         ClientMessage {
-            key: protocol_key.clone(),
             player_move: None,
             request_maze_state: Some(true),
         },
     )
     .await?;
+    let mut decoder = FrameDecoder::new();
     let mut maze_state = String::new();
     let mut flag = None;
     while flag.is_none() {
-        if let Some(response) = read_server_msg(&mut pipe_receiver).await? {
+        for response in read_server_msgs(transport.as_mut(), &mut decoder).await? {
             maze_state = response.maze_state;
             if let Some(rflag) = response.flag {
                 flag.get_or_insert_with(|| rflag.clone());
@@ -105,30 +176,30 @@ async fn main() -> Result<()> {
 
         render_maze_ui(&mut terminal, &maze_state, flag.as_deref())?;
 
-        if event::poll(std::time::Duration::from_millis(50))? {
-            if let Event::Key(key) = event::read()? {
-                let direction = match key.code {
-                    KeyCode::Char('w') | KeyCode::Up => Direction::Up,
-                    KeyCode::Char('a') | KeyCode::Left => Direction::Left,
-                    KeyCode::Char('s') | KeyCode::Down => Direction::Down,
-                    KeyCode::Char('d') | KeyCode::Right => Direction::Right,
-                    KeyCode::Char('q') => break,
-                    _ => continue,
-                };
-
-                send_message(
-                    &mut pipe_sender,
-                    ClientMessage {
-                        key: protocol_key.clone(),
-                        player_move: Some(PlayerMove {
-                            direction: direction.into(),
-                            amount: 1,
-                        }),
-                        request_maze_state: Some(true),
-                    },
-                )
-                .await?;
-            }
+        if event::poll(std::time::Duration::from_millis(50))?
+            && let Event::Key(key) = event::read()?
+        {
+            let direction = match key.code {
+                KeyCode::Char('w') | KeyCode::Up => Direction::Up,
+                KeyCode::Char('a') | KeyCode::Left => Direction::Left,
+                KeyCode::Char('s') | KeyCode::Down => Direction::Down,
+                KeyCode::Char('d') | KeyCode::Right => Direction::Right,
+                KeyCode::Char('q') => break,
+                _ => continue,
+            };
+
+            send_message(
+                transport.as_mut(),
+                &mut signer,
+                ClientMessage {
+                    player_move: Some(PlayerMove {
+                        direction: direction.into(),
+                        amount: 1,
+                    }),
+                    request_maze_state: Some(true),
+                },
+            )
+            .await?;
         }
     }
 
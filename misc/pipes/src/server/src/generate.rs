@@ -0,0 +1,353 @@
+use std::collections::{HashSet, VecDeque};
+use std::str::FromStr;
+
+use anyhow::{Result, bail};
+use rand::seq::{IndexedRandom, SliceRandom};
+use rand::{Rng, RngCore};
+
+pub type Pos = (usize, usize);
+
+/// Gives up on random walls after this many unsolvable layouts in a row.
+const MAX_ATTEMPTS: usize = 1000;
+
+/// What a generator produces. The border of the maze is always wall.
+pub struct Layout {
+    pub walls: HashSet<Pos>,
+    pub start: Pos,
+    pub end: Pos,
+}
+
+pub trait Generator: Send + Sync {
+    fn generate(&self, width: usize, height: usize, rng: &mut dyn RngCore) -> Result<Layout>;
+}
+
+/// Picked with `--generator`. Every one of them can be walked all the way to the flag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    Backtracker,
+    Prim,
+    Kruskal,
+    RandomWalls,
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "backtracker" => Ok(Algorithm::Backtracker),
+            "prim" => Ok(Algorithm::Prim),
+            "kruskal" => Ok(Algorithm::Kruskal),
+            "random-walls" => Ok(Algorithm::RandomWalls),
+            _ => Err(format!(
+                "unknown generator {s}, expected backtracker, prim, kruskal or random-walls"
+            )),
+        }
+    }
+}
+
+impl Algorithm {
+    /// `walls` is only used by random walls.
+    pub fn generator(self, walls: usize) -> Box<dyn Generator> {
+        match self {
+            Algorithm::Backtracker => Box::new(Backtracker),
+            Algorithm::Prim => Box::new(Prim),
+            Algorithm::Kruskal => Box::new(Kruskal),
+            Algorithm::RandomWalls => Box::new(RandomWalls { count: walls }),
+        }
+    }
+}
+
+fn border(width: usize, height: usize) -> HashSet<Pos> {
+    let mut walls = HashSet::new();
+    for y in 0..height {
+        for x in 0..width {
+            if x == 0 || y == 0 || x == width - 1 || y == height - 1 {
+                walls.insert((x, y));
+            }
+        }
+    }
+    walls
+}
+
+/// Whether `end` can be reached from `start` without passing through a wall.
+pub fn solvable(layout: &Layout) -> bool {
+    let mut seen = HashSet::from([layout.start]);
+    let mut queue = VecDeque::from([layout.start]);
+    while let Some((x, y)) = queue.pop_front() {
+        if (x, y) == layout.end {
+            return true;
+        }
+        // The border keeps every neighbour of an open cell in bounds
+        for next in [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)] {
+            if !layout.walls.contains(&next) && seen.insert(next) {
+                queue.push_back(next);
+            }
+        }
+    }
+    false
+}
+
+/// The original layout: `count` walls scattered at random. Regenerates until the player can walk
+/// from the start to the end.
+pub struct RandomWalls {
+    pub count: usize,
+}
+
+impl Generator for RandomWalls {
+    fn generate(&self, width: usize, height: usize, rng: &mut dyn RngCore) -> Result<Layout> {
+        if width < 3 || height < 3 || (width - 2) * (height - 2) < 2 {
+            bail!("a {width}x{height} maze has room for fewer than two cells");
+        }
+        let end = (width - 2, height - 2);
+        for _ in 0..MAX_ATTEMPTS {
+            let mut walls = border(width, height);
+            let mut available_positions: Vec<Pos> = (1..height - 1)
+                .flat_map(|y| (1..width - 1).map(move |x| (x, y)))
+                .filter(|&pos| pos != end)
+                .collect();
+            available_positions.shuffle(rng);
+            for _ in 0..self.count {
+                if let Some(pos) = available_positions.pop() {
+                    walls.insert(pos);
+                }
+            }
+            let Some(start) = available_positions.pop() else {
+                bail!("{} walls leave no room for the player", self.count);
+            };
+            let layout = Layout { walls, start, end };
+            if solvable(&layout) {
+                return Ok(layout);
+            }
+        }
+        bail!(
+            "no solvable maze with {} walls after {MAX_ATTEMPTS} tries",
+            self.count
+        )
+    }
+}
+
+/// The grid the perfect-maze generators carve. Cells sit at odd coordinates and everything else
+/// starts out as wall, so knocking out the wall between two cells joins them.
+struct Cells {
+    cols: usize,
+    rows: usize,
+    walls: HashSet<Pos>,
+}
+
+impl Cells {
+    fn new(width: usize, height: usize) -> Result<Self> {
+        let (cols, rows) = ((width - 1) / 2, (height - 1) / 2);
+        if width < 3 || height < 3 || cols * rows < 2 {
+            bail!("a {width}x{height} maze has room for fewer than two cells");
+        }
+        let walls = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .filter(|&(x, y)| x % 2 == 0 || y % 2 == 0 || x > cols * 2 || y > rows * 2)
+            .collect();
+        Ok(Self { cols, rows, walls })
+    }
+
+    fn index(&self, (col, row): Pos) -> usize {
+        row * self.cols + col
+    }
+
+    fn neighbours(&self, (col, row): Pos) -> impl Iterator<Item = Pos> + use<> {
+        let (cols, rows) = (self.cols, self.rows);
+        [
+            (col.checked_sub(1), Some(row)),
+            (Some(col + 1).filter(|&c| c < cols), Some(row)),
+            (Some(col), row.checked_sub(1)),
+            (Some(col), Some(row + 1).filter(|&r| r < rows)),
+        ]
+        .into_iter()
+        .filter_map(|(c, r)| Some((c?, r?)))
+    }
+
+    fn random_cell(&self, rng: &mut dyn RngCore) -> Pos {
+        (
+            rng.random_range(0..self.cols),
+            rng.random_range(0..self.rows),
+        )
+    }
+
+    fn carve(&mut self, (ac, ar): Pos, (bc, br): Pos) {
+        self.walls.remove(&(ac + bc + 1, ar + br + 1));
+    }
+
+    /// Starts the player in a random cell and puts the end in the bottom right one.
+    fn into_layout(self, rng: &mut dyn RngCore) -> Layout {
+        let (col, row) = self.random_cell(rng);
+        let end = (self.cols * 2 - 1, self.rows * 2 - 1);
+        let start = match (col * 2 + 1, row * 2 + 1) {
+            start if start == end => (1, 1),
+            start => start,
+        };
+        Layout {
+            walls: self.walls,
+            start,
+            end,
+        }
+    }
+}
+
+/// Depth-first search that backs up whenever it walks into a dead end, which gives long winding
+/// corridors.
+pub struct Backtracker;
+
+impl Generator for Backtracker {
+    fn generate(&self, width: usize, height: usize, rng: &mut dyn RngCore) -> Result<Layout> {
+        let mut cells = Cells::new(width, height)?;
+        let first = cells.random_cell(rng);
+        let mut visited = vec![false; cells.cols * cells.rows];
+        visited[cells.index(first)] = true;
+        let mut stack = vec![first];
+        while let Some(&cell) = stack.last() {
+            let unvisited: Vec<Pos> = cells
+                .neighbours(cell)
+                .filter(|&n| !visited[cells.index(n)])
+                .collect();
+            match unvisited.choose(rng) {
+                Some(&next) => {
+                    visited[cells.index(next)] = true;
+                    cells.carve(cell, next);
+                    stack.push(next);
+                }
+                None => {
+                    stack.pop();
+                }
+            }
+        }
+        Ok(cells.into_layout(rng))
+    }
+}
+
+/// Grows the maze from one cell by opening a random passage on its edge each step, which gives
+/// lots of short dead ends.
+pub struct Prim;
+
+impl Generator for Prim {
+    fn generate(&self, width: usize, height: usize, rng: &mut dyn RngCore) -> Result<Layout> {
+        let mut cells = Cells::new(width, height)?;
+        let first = cells.random_cell(rng);
+        let mut visited = vec![false; cells.cols * cells.rows];
+        visited[cells.index(first)] = true;
+        let mut frontier: Vec<(Pos, Pos)> = cells.neighbours(first).map(|n| (first, n)).collect();
+        while !frontier.is_empty() {
+            let (from, to) = frontier.swap_remove(rng.random_range(0..frontier.len()));
+            if visited[cells.index(to)] {
+                continue;
+            }
+            visited[cells.index(to)] = true;
+            cells.carve(from, to);
+            frontier.extend(
+                cells
+                    .neighbours(to)
+                    .filter(|&n| !visited[cells.index(n)])
+                    .map(|n| (to, n)),
+            );
+        }
+        Ok(cells.into_layout(rng))
+    }
+}
+
+/// Opens passages in random order, skipping any that would join two cells already connected.
+pub struct Kruskal;
+
+fn find(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+impl Generator for Kruskal {
+    fn generate(&self, width: usize, height: usize, rng: &mut dyn RngCore) -> Result<Layout> {
+        let mut cells = Cells::new(width, height)?;
+        let mut edges: Vec<(Pos, Pos)> = (0..cells.rows)
+            .flat_map(|row| (0..cells.cols).map(move |col| (col, row)))
+            .flat_map(|cell| {
+                // Only right and down, so every edge is listed once
+                cells
+                    .neighbours(cell)
+                    .filter(move |&n| n > cell)
+                    .map(move |n| (cell, n))
+            })
+            .collect();
+        edges.shuffle(rng);
+        let mut parents: Vec<usize> = (0..cells.cols * cells.rows).collect();
+        for (a, b) in edges {
+            let (ra, rb) = (
+                find(&mut parents, cells.index(a)),
+                find(&mut parents, cells.index(b)),
+            );
+            if ra != rb {
+                parents[ra] = rb;
+                cells.carve(a, b);
+            }
+        }
+        Ok(cells.into_layout(rng))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use super::*;
+
+    const ALGORITHMS: [Algorithm; 4] = [
+        Algorithm::Backtracker,
+        Algorithm::Prim,
+        Algorithm::Kruskal,
+        Algorithm::RandomWalls,
+    ];
+
+    #[test]
+    fn every_algorithm_is_solvable() {
+        for algorithm in ALGORITHMS {
+            let generator = algorithm.generator(500);
+            for seed in 0..100 {
+                let layout = generator
+                    .generate(100, 30, &mut StdRng::seed_from_u64(seed))
+                    .unwrap();
+                assert!(solvable(&layout), "{algorithm:?} with seed {seed}");
+                assert!(!layout.walls.contains(&layout.start));
+                assert!(!layout.walls.contains(&layout.end));
+            }
+        }
+    }
+
+    #[test]
+    fn random_walls_can_always_be_walked_to_the_end() {
+        for seed in 0..50 {
+            let layout = RandomWalls { count: 1200 }
+                .generate(100, 30, &mut StdRng::seed_from_u64(seed))
+                .unwrap();
+            assert_eq!(layout.end, (98, 28));
+            assert!(solvable(&layout), "seed {seed}");
+        }
+    }
+
+    #[test]
+    fn perfect_mazes_have_no_loops() {
+        for algorithm in &ALGORITHMS[..3] {
+            let layout = algorithm
+                .generator(0)
+                .generate(100, 30, &mut StdRng::seed_from_u64(7))
+                .unwrap();
+            let open: Vec<Pos> = (0..30)
+                .flat_map(|y| (0..100).map(move |x| (x, y)))
+                .filter(|p| !layout.walls.contains(p))
+                .collect();
+            let passages = open
+                .iter()
+                .flat_map(|&(x, y)| [(x + 1, y), (x, y + 1)])
+                .filter(|p| !layout.walls.contains(p))
+                .count();
+            assert_eq!(passages, open.len() - 1, "{algorithm:?}");
+        }
+    }
+}
@@ -1,21 +1,96 @@
+mod generate;
+
 use std::collections::HashSet;
+use std::fs::Permissions;
+use std::io::ErrorKind;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
-use anyhow::Context;
 use anyhow::Result;
+use anyhow::{Context, bail};
+use nix::fcntl::OFlag;
 use nix::sys::stat::Mode;
-use nix::sys::stat::umask;
-use nix::unistd::mkfifo;
-use prost::Message as _;
-use rand::seq::SliceRandom;
+use nix::unistd::{getegid, geteuid, mkfifo};
 use shared::{
-    ClientMessage, ServerMessage, client_message::PlayerMove,
-    client_message::player_move::Direction,
+    Fifo, FrameDecoder, MacKey, ServerMessage, SessionPaths, SignedMessage, Transport,
+    TransportKind, Verifier, check_owned, client_message::PlayerMove,
+    client_message::player_move::Direction, encode_frame, session_root,
 };
 use tokio::fs::{self, OpenOptions};
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
-use tokio::net::unix::pipe::{self, Receiver, Sender};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixListener;
+use tokio::net::unix::pipe;
+use tokio::task::JoinSet;
+
+use crate::generate::{Algorithm, Generator};
+
+struct Args {
+    dir: Option<PathBuf>,
+    mazes: usize,
+    transport: TransportKind,
+    allowed: Peer,
+    generator: Algorithm,
+    walls: usize,
+}
+
+impl Args {
+    fn parse() -> Result<Self> {
+        let mut args = Args {
+            dir: None,
+            mazes: 1,
+            transport: TransportKind::Fifo,
+            // The client runs setuid as the server's user, so by default only it may connect
+            allowed: Peer {
+                uid: geteuid().as_raw(),
+                gid: getegid().as_raw(),
+            },
+            generator: Algorithm::RandomWalls,
+            walls: 500,
+        };
+        let mut argv = std::env::args().skip(1);
+        while let Some(arg) = argv.next() {
+            let mut value = || argv.next().with_context(|| format!("{arg} needs a value"));
+            match arg.as_str() {
+                "--dir" => args.dir = Some(value()?.into()),
+                "--mazes" => args.mazes = value()?.parse().context("--mazes takes a number")?,
+                "--transport" => args.transport = value()?.parse().map_err(anyhow::Error::msg)?,
+                "--allow-uid" => {
+                    args.allowed.uid = value()?.parse().context("--allow-uid takes a number")?
+                }
+                "--generator" => args.generator = value()?.parse().map_err(anyhow::Error::msg)?,
+                "--walls" => args.walls = value()?.parse().context("--walls takes a number")?,
+                "--allow-gid" => {
+                    args.allowed.gid = value()?.parse().context("--allow-gid takes a number")?
+                }
+                _ => bail!(
+                    "unknown argument {arg}, usage: server [--dir <path>] [--mazes <n>] \
+                     [--transport fifo|socket] [--allow-uid <uid>] [--allow-gid <gid>] \
+                     [--generator backtracker|prim|kruskal|random-walls] [--walls <n>]"
+                ),
+            }
+        }
+        if args.mazes == 0 {
+            bail!("--mazes must be at least 1");
+        }
+        Ok(args)
+    }
+}
 
-async fn initialize_key() -> Result<[u8; 16]> {
+/// Creates `dir` unless it exists, then makes sure it is ours. Someone else's directory could
+/// hold links that point the key or FIFOs at files of their choosing.
+fn create_owned_dir(dir: &Path) -> Result<()> {
+    match std::fs::DirBuilder::new().mode(0o755).create(dir) {
+        Err(e) if e.kind() != ErrorKind::AlreadyExists => {
+            return Err(e).with_context(|| format!("creating {}", dir.display()));
+        }
+        _ => {}
+    }
+    check_owned(dir).with_context(|| format!("refusing {}", dir.display()))
+}
+
+async fn initialize_key(keypath: &Path) -> Result<[u8; 16]> {
     let mut buf = [0; 16];
     let mut urandom = OpenOptions::new()
         .read(true)
@@ -23,9 +98,10 @@ async fn initialize_key() -> Result<[u8; 16]> {
         .await
         .context("reading urandom")?;
     urandom.read_exact(&mut buf).await?;
-    let keypath = shared::KEY_LOCATION;
+    let _ = fs::remove_file(keypath).await;
     let mut keyfile = OpenOptions::new()
-        .create(true)
+        .create_new(true)
+        .custom_flags(OFlag::O_NOFOLLOW.bits())
         .mode(0o400)
         .write(true)
         .open(keypath)
@@ -35,34 +111,97 @@ async fn initialize_key() -> Result<[u8; 16]> {
     Ok(buf)
 }
 
-async fn open_pipe() -> Result<(Receiver, Sender)> {
-    let pipe_in_path = shared::PIPE_IN_LOCATION;
-    let pipe_out_path = shared::PIPE_OUT_LOCATION;
+async fn create_pipes(paths: &SessionPaths) -> Result<()> {
+    let pipe_in_path = &paths.pipe_in();
+    let pipe_out_path = &paths.pipe_out();
 
     let _ = fs::remove_file(pipe_in_path).await;
     let _ = fs::remove_file(pipe_out_path).await;
 
-    let old_umask = umask(Mode::empty());
-    mkfifo(
-        pipe_in_path,
-        Mode::S_IRUSR | Mode::S_IWUSR | Mode::S_IWGRP | Mode::S_IWOTH,
-    )?;
-    mkfifo(
-        pipe_out_path,
-        Mode::S_IRUSR | Mode::S_IWUSR | Mode::S_IWGRP | Mode::S_IWOTH,
-    )?;
-    umask(old_umask);
+    // chmod rather than clearing the umask, which is shared by every maze being set up at once
+    let mode = Mode::S_IRUSR | Mode::S_IWUSR | Mode::S_IWGRP | Mode::S_IWOTH;
+    for path in [pipe_in_path, pipe_out_path] {
+        mkfifo(path, mode)?;
+        fs::set_permissions(path, Permissions::from_mode(mode.bits())).await?;
+    }
+    Ok(())
+}
 
+fn open_pipe(paths: &SessionPaths) -> Result<Fifo> {
     let receiver = pipe::OpenOptions::new()
         .read_write(true)
-        .open_receiver(pipe_in_path)
+        .open_receiver(paths.pipe_in())
         .context("opening named pipe for reading")?;
     let sender = pipe::OpenOptions::new()
         .read_write(true)
-        .open_sender(pipe_out_path)
+        .open_sender(paths.pipe_out())
         .context("opening named pipe for writing")?;
 
-    Ok((receiver, sender))
+    Ok(Fifo::new(receiver, sender))
+}
+
+/// The credentials a socket client must connect with.
+#[derive(Clone, Copy)]
+struct Peer {
+    uid: u32,
+    gid: u32,
+}
+
+/// Where a maze's clients come from.
+enum Endpoint {
+    Fifo(SessionPaths),
+    Socket {
+        listener: UnixListener,
+        allowed: Peer,
+    },
+}
+
+impl Endpoint {
+    async fn create(kind: TransportKind, paths: SessionPaths, allowed: Peer) -> Result<Self> {
+        match kind {
+            TransportKind::Fifo => {
+                create_pipes(&paths).await?;
+                Ok(Endpoint::Fifo(paths))
+            }
+            TransportKind::Socket => {
+                let path = paths.socket();
+                let _ = fs::remove_file(&path).await;
+                let listener = UnixListener::bind(&path).context("binding socket")?;
+                // Anyone may connect, the peer credentials decide who gets to play
+                fs::set_permissions(&path, Permissions::from_mode(0o666)).await?;
+                Ok(Endpoint::Socket { listener, allowed })
+            }
+        }
+    }
+
+    /// Waits for the next client. The FIFOs aren't tied to a client, so they are just reopened.
+    async fn accept(&mut self) -> Result<Box<dyn Transport>> {
+        match self {
+            Endpoint::Fifo(paths) => Ok(Box::new(open_pipe(paths)?)),
+            Endpoint::Socket { listener, allowed } => loop {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    // Usually out of file descriptors, which may pass once other clients leave
+                    Err(e) => {
+                        eprintln!("accepting client: {e}");
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
+                };
+                match stream.peer_cred() {
+                    Ok(cred) if cred.uid() == allowed.uid && cred.gid() == allowed.gid => {
+                        return Ok(Box::new(stream));
+                    }
+                    Ok(cred) => eprintln!(
+                        "refusing connection from uid {} gid {}",
+                        cred.uid(),
+                        cred.gid()
+                    ),
+                    Err(e) => eprintln!("refusing connection: {e}"),
+                }
+            },
+        }
+    }
 }
 
 struct Maze {
@@ -74,39 +213,15 @@ struct Maze {
 }
 
 impl Maze {
-    fn generate(width: usize, height: usize, wall_count: usize) -> Self {
-        let mut walls = HashSet::new();
-        let mut rng = rand::rng();
-
-        for y in 0..height {
-            for x in 0..width {
-                if x == 0 || y == 0 || x == width - 10 || y == height - 1 {
-                    walls.insert((x, y));
-                }
-            }
-        }
-
-        let mut available_positions: Vec<(usize, usize)> = (1..height - 1)
-            .flat_map(|y| (1..width - 10).map(move |x| (x, y)))
-            .collect();
-
-        available_positions.shuffle(&mut rng);
-
-        for _ in 0..wall_count {
-            if let Some(pos) = available_positions.pop() {
-                walls.insert(pos);
-            }
-        }
-
-        let player_pos = available_positions.pop().unwrap();
-        let end_pos = (width - 2, height - 2);
-        Self {
+    fn generate(width: usize, height: usize, generator: &dyn Generator) -> Result<Self> {
+        let layout = generator.generate(width, height, &mut rand::rng())?;
+        Ok(Self {
             width,
             height,
-            player_pos,
-            end_pos,
-            walls,
-        }
+            player_pos: layout.start,
+            end_pos: layout.end,
+            walls: layout.walls,
+        })
     }
 
     fn get_cell(&self, pos: (usize, usize)) -> char {
@@ -150,48 +265,111 @@ impl Maze {
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let flag = fs::read_to_string("/flag.txt")
-        .await
-        .unwrap_or_else(|_| "corctf{fake_flag_for_testing}".to_string());
-    let key = initialize_key().await?;
-    let (pipe_receiver, mut pipe_sender) = open_pipe().await?;
+/// Plays one maze until it's solved.
+async fn run_maze(paths: SessionPaths, args: &Args, flag: String) -> Result<()> {
+    let mut maze = Maze::generate(100, 30, args.generator.generator(args.walls).as_ref())?;
+    create_owned_dir(&paths.dir)?;
+    let mut verifier = Verifier::new(MacKey::derive(&initialize_key(&paths.key()).await?));
+    let dir = paths.dir.clone();
+    let mut endpoint = Endpoint::create(args.transport, paths, args.allowed).await?;
+    eprintln!("maze ready in {}", dir.display());
 
-    let mut maze = Maze::generate(100, 30, 500);
-    let mut reader = BufReader::new(pipe_receiver);
     let mut buf = vec![0; 1024];
     let mut won = false;
     let mut maze_state = maze.render();
 
-    while !won {
-        let n = reader.read(&mut buf).await?;
-        if n == 0 {
-            continue;
-        }
-        let Ok(msg) = ClientMessage::decode(&buf[..n]) else {
-            continue;
-        };
-        if msg.key != key {
-            continue;
-        }
+    // The maze carries on where it was when a client hangs up and another connects
+    'clients: while !won {
+        let mut transport = endpoint.accept().await?;
+        let mut decoder = FrameDecoder::new();
+        while !won {
+            let n = match transport.read(&mut buf).await {
+                Ok(0) => continue 'clients,
+                Ok(n) => n,
+                Err(e) => {
+                    eprintln!("dropping client: {e}");
+                    continue 'clients;
+                }
+            };
+            decoder.push(&buf[..n]);
 
-        if let Some(player_move) = msg.player_move {
-            maze.move_player(player_move);
-        }
+            // One read may hold several messages, or only part of one
+            while !won {
+                let signed = match decoder.next_frame::<SignedMessage>() {
+                    Ok(Some(signed)) => signed,
+                    Ok(None) => break,
+                    Err(e) => {
+                        eprintln!("dropping message: {e}");
+                        continue;
+                    }
+                };
+                let msg = match verifier.open(signed) {
+                    Ok(msg) => msg,
+                    Err(e) => {
+                        eprintln!("rejecting message: {e}");
+                        continue;
+                    }
+                };
 
-        if msg.request_maze_state() {
-            maze_state = maze.render();
+                if let Some(player_move) = msg.player_move {
+                    maze.move_player(player_move);
+                }
+
+                if msg.request_maze_state() {
+                    maze_state = maze.render();
+                }
+
+                won = maze.player_pos == maze.end_pos;
+                let flag = won.then(|| flag.clone());
+                let response = ServerMessage {
+                    flag,
+                    maze_state: maze_state.clone(),
+                };
+                if let Err(e) = transport.write_all(&encode_frame(&response)).await {
+                    eprintln!("dropping client: {e}");
+                    continue 'clients;
+                }
+            }
         }
+    }
+    Ok(())
+}
 
-        won = maze.player_pos == maze.end_pos;
-        let flag = won.then(|| flag.clone());
-        let response = ServerMessage {
-            flag,
-            maze_state: maze_state.clone(),
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse()?;
+    let flag = fs::read_to_string("/flag.txt")
+        .await
+        .unwrap_or_else(|_| "corctf{fake_flag_for_testing}".to_string());
+    let root = session_root(args.dir.clone());
+    create_owned_dir(&root)?;
+    let args = Arc::new(args);
+
+    // Every maze has its own key and FIFOs or socket; the server exits once all of them are solved
+    let mut mazes = JoinSet::new();
+    for n in 0..args.mazes {
+        let paths = SessionPaths::new(&root, n);
+        let (args, flag) = (args.clone(), flag.clone());
+        mazes.spawn(async move {
+            let dir = paths.dir.clone();
+            run_maze(paths, &args, flag)
+                .await
+                .with_context(|| format!("maze in {}", dir.display()))
+        });
+    }
+    // A broken maze shouldn't end the game for the others
+    let mut failed = 0;
+    while let Some(done) = mazes.join_next().await {
+        let err = match done {
+            Ok(Ok(())) => continue,
+            Ok(Err(e)) => e,
+            Err(e) => e.into(),
         };
-        let response_buf = response.encode_to_vec();
-        pipe_sender.write_all(&response_buf).await?;
+        eprintln!("{err:#}");
+        failed += 1;
+    }
+    if failed == args.mazes {
+        bail!("no maze could be played");
     }
     Ok(())
 }
@@ -0,0 +1,198 @@
+use std::fmt;
+
+use hmac::{Hmac, Mac};
+use prost::Message;
+use sha2::Sha256;
+
+use crate::{ClientMessage, SignedMessage};
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Keeps MACs from being usable for anything but client messages
+const MAC_KEY_LABEL: &[u8] = b"pipes client message mac v1";
+
+/// The key client messages are authenticated with, derived from the contents of the key file.
+#[derive(Clone)]
+pub struct MacKey([u8; 32]);
+
+impl MacKey {
+    pub fn derive(key_file: &[u8]) -> Self {
+        let mut mac = HmacSha256::new_from_slice(key_file).expect("HMAC takes keys of any length");
+        mac.update(MAC_KEY_LABEL);
+        Self(mac.finalize().into_bytes().into())
+    }
+
+    fn mac(&self, seq: u64, payload: &[u8]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.0).expect("HMAC takes keys of any length");
+        mac.update(&seq.to_be_bytes());
+        mac.update(payload);
+        mac
+    }
+
+    /// Wraps `msg` for sending as message number `seq`.
+    pub fn seal(&self, seq: u64, msg: &ClientMessage) -> SignedMessage {
+        let payload = msg.encode_to_vec();
+        let mac = self.mac(seq, &payload).finalize().into_bytes().to_vec();
+        SignedMessage { seq, payload, mac }
+    }
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    BadMac {
+        seq: u64,
+    },
+    /// `seq` was not above the last accepted one, so the message is a replay or arrived out of order.
+    Stale {
+        seq: u64,
+        last: u64,
+    },
+    Decode(prost::DecodeError),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuthError::BadMac { seq } => write!(f, "message {seq} has a bad MAC"),
+            AuthError::Stale { seq, last } => {
+                write!(
+                    f,
+                    "message {seq} is replayed or out of order, last accepted was {last}"
+                )
+            }
+            AuthError::Decode(e) => write!(f, "authenticated payload didn't decode: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+/// Checks incoming messages on the server side. Sequence numbers must strictly increase, so each
+/// signed message is accepted at most once.
+pub struct Verifier {
+    key: MacKey,
+    last_seq: Option<u64>,
+}
+
+impl Verifier {
+    pub fn new(key: MacKey) -> Self {
+        Self {
+            key,
+            last_seq: None,
+        }
+    }
+
+    pub fn open(&mut self, signed: SignedMessage) -> Result<ClientMessage, AuthError> {
+        let seq = signed.seq;
+        self.key
+            .mac(seq, &signed.payload)
+            .verify_slice(&signed.mac)
+            .map_err(|_| AuthError::BadMac { seq })?;
+        if let Some(last) = self.last_seq.filter(|&last| seq <= last) {
+            return Err(AuthError::Stale { seq, last });
+        }
+        let msg = ClientMessage::decode(signed.payload.as_slice()).map_err(AuthError::Decode)?;
+        self.last_seq = Some(seq);
+        Ok(msg)
+    }
+}
+
+/// Numbers the messages a client sends. Starting from the current time keeps the numbers
+/// increasing across client restarts, which the server's `Verifier` requires.
+pub struct Signer {
+    key: MacKey,
+    next_seq: u64,
+}
+
+impl Signer {
+    pub fn new(key: MacKey, start: u64) -> Self {
+        Self {
+            key,
+            next_seq: start,
+        }
+    }
+
+    pub fn sign(&mut self, msg: &ClientMessage) -> SignedMessage {
+        let signed = self.key.seal(self.next_seq, msg);
+        self.next_seq += 1;
+        signed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> MacKey {
+        MacKey::derive(b"0123456789abcdef")
+    }
+
+    fn message() -> ClientMessage {
+        ClientMessage {
+            player_move: None,
+            request_maze_state: Some(true),
+        }
+    }
+
+    #[test]
+    fn rejects_a_replay() {
+        let mut verifier = Verifier::new(key());
+        let signed = key().seal(5, &message());
+        assert_eq!(verifier.open(signed.clone()).unwrap(), message());
+        assert!(matches!(
+            verifier.open(signed),
+            Err(AuthError::Stale { seq: 5, last: 5 })
+        ));
+    }
+
+    #[test]
+    fn rejects_messages_out_of_order() {
+        let mut verifier = Verifier::new(key());
+        verifier.open(key().seal(7, &message())).unwrap();
+        assert!(matches!(
+            verifier.open(key().seal(6, &message())),
+            Err(AuthError::Stale { seq: 6, last: 7 })
+        ));
+        verifier.open(key().seal(8, &message())).unwrap();
+    }
+
+    #[test]
+    fn rejects_a_tampered_message() {
+        let mut verifier = Verifier::new(key());
+        let mut signed = key().seal(1, &message());
+        signed.payload.push(0);
+        assert!(matches!(
+            verifier.open(signed),
+            Err(AuthError::BadMac { seq: 1 })
+        ));
+        let mut signed = key().seal(1, &message());
+        signed.mac[0] ^= 1;
+        assert!(matches!(
+            verifier.open(signed),
+            Err(AuthError::BadMac { seq: 1 })
+        ));
+        let forged = MacKey::derive(b"not the key").seal(1, &message());
+        assert!(matches!(
+            verifier.open(forged),
+            Err(AuthError::BadMac { seq: 1 })
+        ));
+        // None of those used up the sequence number
+        verifier.open(key().seal(1, &message())).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_sequence_when_the_payload_does_not_decode() {
+        let mut verifier = Verifier::new(key());
+        verifier.open(key().seal(1, &message())).unwrap();
+        let payload = vec![0xff];
+        let mac = key().mac(2, &payload).finalize().into_bytes().to_vec();
+        let garbage = SignedMessage {
+            seq: 2,
+            payload,
+            mac,
+        };
+        assert!(matches!(verifier.open(garbage), Err(AuthError::Decode(_))));
+        assert_eq!(verifier.last_seq, Some(1));
+        verifier.open(key().seal(2, &message())).unwrap();
+    }
+}
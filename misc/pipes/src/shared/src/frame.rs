@@ -0,0 +1,142 @@
+use std::fmt;
+
+use prost::Message;
+
+/// Frames larger than this are treated as garbage rather than buffered.
+pub const MAX_FRAME_LEN: usize = 64 * 1024;
+
+/// Encodes `msg` as a varint length prefix followed by the message bytes.
+pub fn encode_frame<M: Message>(msg: &M) -> Vec<u8> {
+    msg.encode_length_delimited_to_vec()
+}
+
+#[derive(Debug)]
+pub enum FrameError {
+    /// The length prefix is not a valid varint or exceeds `MAX_FRAME_LEN`. The stream can't be
+    /// resynchronised after this, so everything buffered is dropped.
+    BadLength,
+    /// The frame was complete but its contents didn't decode. Only that frame is skipped.
+    Decode(prost::DecodeError),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FrameError::BadLength => write!(f, "invalid frame length"),
+            FrameError::Decode(e) => write!(f, "invalid frame: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+/// Reassembles length-delimited frames from a byte stream, however the reads happen to split or
+/// coalesce them.
+#[derive(Default)]
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends bytes read from the stream.
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Returns the next complete frame, or `None` if more bytes are needed.
+    pub fn next_frame<M: Message + Default>(&mut self) -> Result<Option<M>, FrameError> {
+        // A length varint is at most 10 bytes; wait until we have its last byte
+        let Some(end) = self.buf.iter().take(10).position(|b| b & 0x80 == 0) else {
+            if self.buf.len() >= 10 {
+                self.buf.clear();
+                return Err(FrameError::BadLength);
+            }
+            return Ok(None);
+        };
+        let header = end + 1;
+        let len = match prost::decode_length_delimiter(&self.buf[..header]) {
+            Ok(len) if len <= MAX_FRAME_LEN => len,
+            _ => {
+                self.buf.clear();
+                return Err(FrameError::BadLength);
+            }
+        };
+        if self.buf.len() < header + len {
+            return Ok(None);
+        }
+        let frame = M::decode(&self.buf[header..header + len]);
+        self.buf.drain(..header + len);
+        frame.map(Some).map_err(FrameError::Decode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ServerMessage;
+
+    fn message(n: usize) -> ServerMessage {
+        ServerMessage {
+            maze_state: format!("maze {n}"),
+            flag: None,
+        }
+    }
+
+    #[test]
+    fn reassembles_a_frame_fed_byte_by_byte() {
+        let bytes = encode_frame(&message(1));
+        let mut decoder = FrameDecoder::new();
+        for (i, b) in bytes.iter().enumerate() {
+            assert!(
+                decoder.next_frame::<ServerMessage>().unwrap().is_none(),
+                "byte {i}"
+            );
+            decoder.push(&[*b]);
+        }
+        assert_eq!(decoder.next_frame().unwrap(), Some(message(1)));
+        assert!(decoder.next_frame::<ServerMessage>().unwrap().is_none());
+    }
+
+    #[test]
+    fn splits_two_frames_pushed_at_once() {
+        let mut bytes = encode_frame(&message(1));
+        bytes.extend(encode_frame(&message(2)));
+        let mut decoder = FrameDecoder::new();
+        decoder.push(&bytes);
+        assert_eq!(decoder.next_frame().unwrap(), Some(message(1)));
+        assert_eq!(decoder.next_frame().unwrap(), Some(message(2)));
+        assert!(decoder.next_frame::<ServerMessage>().unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_an_oversized_length_without_waiting_for_it() {
+        let mut bytes = Vec::new();
+        prost::encode_length_delimiter(MAX_FRAME_LEN + 1, &mut bytes).unwrap();
+        let mut decoder = FrameDecoder::new();
+        decoder.push(&bytes);
+        assert!(matches!(
+            decoder.next_frame::<ServerMessage>(),
+            Err(FrameError::BadLength)
+        ));
+        // The bad prefix is dropped, so a later frame still gets through
+        decoder.push(&encode_frame(&message(1)));
+        assert_eq!(decoder.next_frame().unwrap(), Some(message(1)));
+    }
+
+    #[test]
+    fn rejects_a_length_that_never_ends() {
+        let mut decoder = FrameDecoder::new();
+        decoder.push(&[0x80; 9]);
+        assert!(decoder.next_frame::<ServerMessage>().unwrap().is_none());
+        decoder.push(&[0x80]);
+        assert!(matches!(
+            decoder.next_frame::<ServerMessage>(),
+            Err(FrameError::BadLength)
+        ));
+        assert!(decoder.next_frame::<ServerMessage>().unwrap().is_none());
+    }
+}
@@ -1,5 +1,12 @@
 include!(concat!(env!("OUT_DIR"), "/pipes.rs"));
-pub static PIPE_IN_LOCATION: &str = "/tmp/pipe1";
-pub static PIPE_OUT_LOCATION: &str = "/tmp/pipe2";
-pub static KEY_LOCATION: &str = "/tmp/key";
+
+mod auth;
+mod frame;
+mod session;
+mod transport;
+pub use auth::{AuthError, MacKey, Signer, Verifier};
+pub use frame::{FrameDecoder, FrameError, MAX_FRAME_LEN, encode_frame};
+pub use session::{DIR_ENV, SessionPaths, check_owned, session_root};
+pub use transport::{Fifo, Transport, TransportKind};
+
 impl Copy for client_message::PlayerMove {}
@@ -0,0 +1,77 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use nix::unistd::{getegid, geteuid, getgid, getuid};
+
+/// Overrides where game sessions live, unless `--dir` is given.
+pub const DIR_ENV: &str = "PIPES_DIR";
+
+/// Picks the directory holding every maze's session: `--dir` if given, then `$PIPES_DIR`, then
+/// `$XDG_RUNTIME_DIR/pipes`, then `/tmp/pipes`. A setuid run skips the environment, which belongs
+/// to whoever started it.
+pub fn session_root(cli: Option<PathBuf>) -> PathBuf {
+    let env_var = |name| env::var_os(name).filter(|_| !setuid());
+    cli.or_else(|| env_var(DIR_ENV).map(PathBuf::from))
+        .or_else(|| env_var("XDG_RUNTIME_DIR").map(|dir| PathBuf::from(dir).join("pipes")))
+        .unwrap_or_else(|| PathBuf::from("/tmp/pipes"))
+}
+
+fn setuid() -> bool {
+    geteuid() != getuid() || getegid() != getgid()
+}
+
+/// Fails unless `dir` is a real directory owned by our effective user. Anyone can create
+/// `/tmp/pipes` or point `--dir` somewhere, so a directory we didn't make can't be trusted with
+/// the key.
+pub fn check_owned(dir: &Path) -> io::Result<()> {
+    let meta = fs::symlink_metadata(dir)?;
+    if !meta.is_dir() {
+        return Err(io::Error::other(format!(
+            "{} is not a directory",
+            dir.display()
+        )));
+    }
+    if meta.uid() != geteuid().as_raw() {
+        return Err(io::Error::other(format!(
+            "{} belongs to uid {}, not us",
+            dir.display(),
+            meta.uid()
+        )));
+    }
+    Ok(())
+}
+
+/// The key, FIFO pair and socket of one maze, kept in their own directory under the session root.
+pub struct SessionPaths {
+    pub dir: PathBuf,
+}
+
+impl SessionPaths {
+    pub fn new(root: &Path, maze: usize) -> Self {
+        Self {
+            dir: root.join(format!("maze-{maze}")),
+        }
+    }
+
+    /// Client to server messages.
+    pub fn pipe_in(&self) -> PathBuf {
+        self.dir.join("in")
+    }
+
+    /// Server to client messages.
+    pub fn pipe_out(&self) -> PathBuf {
+        self.dir.join("out")
+    }
+
+    /// Used instead of the FIFOs by the socket transport.
+    pub fn socket(&self) -> PathBuf {
+        self.dir.join("socket")
+    }
+
+    pub fn key(&self) -> PathBuf {
+        self.dir.join("key")
+    }
+}
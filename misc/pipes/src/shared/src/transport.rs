@@ -0,0 +1,87 @@
+use std::io;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::UnixStream;
+use tokio::net::unix::pipe::{Receiver, Sender};
+
+/// A byte stream between a client and the server of one maze. Messages on it are framed with
+/// `encode_frame` and read back with a `FrameDecoder`.
+pub trait Transport: AsyncRead + AsyncWrite + Unpin + Send {
+    /// Reads whatever is available without waiting, failing with `WouldBlock` if nothing is.
+    fn try_read(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransportKind {
+    /// A pair of named pipes, one per direction.
+    Fifo,
+    /// A Unix domain socket, which lets the server check who is connecting.
+    Socket,
+}
+
+impl FromStr for TransportKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fifo" => Ok(TransportKind::Fifo),
+            "socket" => Ok(TransportKind::Socket),
+            _ => Err(format!("unknown transport {s}, expected fifo or socket")),
+        }
+    }
+}
+
+/// Reads from one named pipe and writes to the other.
+pub struct Fifo {
+    receiver: Receiver,
+    sender: Sender,
+}
+
+impl Fifo {
+    pub fn new(receiver: Receiver, sender: Sender) -> Self {
+        Self { receiver, sender }
+    }
+}
+
+impl AsyncRead for Fifo {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.receiver).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Fifo {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.sender).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.sender).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.sender).poll_shutdown(cx)
+    }
+}
+
+impl Transport for Fifo {
+    fn try_read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.receiver.try_read(buf)
+    }
+}
+
+impl Transport for UnixStream {
+    fn try_read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        UnixStream::try_read(self, buf)
+    }
+}